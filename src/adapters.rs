@@ -0,0 +1,159 @@
+use bio::io::fasta::{self, FastaRead};
+use memchr::memmem::Finder;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Built-in adapters, searched as in FastQC by their first 12 bases,
+/// and whether they are read through at the 3' end of the reads
+const BUILTIN_ADAPTERS: [(&str, &str, bool); 7] = [
+    ("Illumina Universal Adapter (TruSeq)", "AGATCGGAAGAG", true),
+    ("Illumina Small RNA 3' Adapter", "TGGAATTCTCGG", true),
+    ("Illumina Small RNA 5' Adapter", "GATCGTCGGACT", false),
+    ("Nextera Transposase Sequence", "CTGTCTCTTATA", true),
+    ("SOLiD Small RNA Adapter", "CGCCTTGGCCGT", true),
+    ("Nanopore Ligation Adapter", "AATGTACTTCGT", false),
+    ("Nanopore Rapid Adapter", "GTTTTCGCATTT", false),
+];
+
+#[derive(Clone)]
+pub struct Adapter {
+    pub name: String,
+    /// Upper case sequence
+    pub seq: Vec<u8>,
+    /// Found at the 3' end of the reads, so it can be trimmed from there
+    pub three_prime: bool,
+    finder: Finder<'static>,
+}
+
+impl Adapter {
+    pub fn new(name: &str, seq: &[u8], three_prime: bool) -> Self {
+        let seq = seq.to_ascii_uppercase();
+        let finder = Finder::new(&seq).into_owned();
+        Adapter {
+            name: name.to_string(),
+            seq,
+            three_prime,
+            finder,
+        }
+    }
+
+    /// Position of the first occurrence of the adapter in an upper case read.
+    pub fn find(&self, seq: &[u8]) -> Option<usize> {
+        self.finder.find(seq)
+    }
+}
+
+pub fn builtin_adapters() -> Vec<Adapter> {
+    BUILTIN_ADAPTERS
+        .iter()
+        .map(|(name, seq, three_prime)| Adapter::new(name, seq.as_bytes(), *three_prime))
+        .collect()
+}
+
+/// Reads adapters from a FASTA file, the record IDs are used as names.
+/// They are taken as 3' adapters.
+pub fn read_adapters(path: &str) -> Result<Vec<Adapter>, Box<dyn std::error::Error>> {
+    let mut reader = fasta::Reader::from_file(path)
+        .map_err(|error| format!("Could not open adapter file {}: {}", path, error))?;
+    let mut record = fasta::Record::new();
+    let mut adapters = Vec::new();
+    loop {
+        reader.read(&mut record)?;
+        if record.is_empty() {
+            break;
+        }
+        if record.seq().is_empty() {
+            return Err(format!("Empty adapter {} in {}", record.id(), path).into());
+        }
+        adapters.push(Adapter::new(record.id(), record.seq(), true));
+    }
+    Ok(adapters)
+}
+
+/// Upper case version of a read, only copied if needed
+pub fn to_upper(seq: &[u8]) -> Cow<'_, [u8]> {
+    if seq.iter().any(u8::is_ascii_lowercase) {
+        Cow::Owned(seq.to_ascii_uppercase())
+    } else {
+        Cow::Borrowed(seq)
+    }
+}
+
+/// Counts of the reads in which an adapter is first found at each position
+#[derive(Clone)]
+pub struct AdapterHits {
+    pub name: String,
+    pub seq: Vec<u8>,
+    pub start_counts: Vec<usize>,
+}
+
+impl AdapterHits {
+    pub fn new(adapter: &Adapter) -> Self {
+        AdapterHits {
+            name: adapter.name.clone(),
+            seq: adapter.seq.clone(),
+            start_counts: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the hits from their summary, as read from a stats file.
+    pub fn from_content(content: &AdapterContent, total_reads: usize) -> Self {
+        let mut previous_cumulative = 0;
+        let start_counts = content
+            .cumulative_percent_by_position
+            .iter()
+            .map(|percent| {
+                let cumulative = (percent / 100.0 * total_reads as f64).round() as usize;
+                let count = cumulative.saturating_sub(previous_cumulative);
+                previous_cumulative = cumulative;
+                count
+            })
+            .collect();
+        AdapterHits {
+            name: content.name.clone(),
+            seq: content.sequence.as_bytes().to_vec(),
+            start_counts,
+        }
+    }
+
+    pub fn add_hit(&mut self, position: usize) {
+        if self.start_counts.len() <= position {
+            self.start_counts.resize(position + 1, 0);
+        }
+        self.start_counts[position] += 1;
+    }
+
+    pub fn merge(&mut self, other: &AdapterHits) {
+        if self.start_counts.len() < other.start_counts.len() {
+            self.start_counts.resize(other.start_counts.len(), 0);
+        }
+        for (count, other_count) in self.start_counts.iter_mut().zip(&other.start_counts) {
+            *count += other_count;
+        }
+    }
+
+    /// Cumulative percentage of reads with the adapter starting at or before each position,
+    /// for positions up to `max_len`.
+    pub fn summarize(&self, total_reads: usize, max_len: usize) -> AdapterContent {
+        let mut cumulative = 0;
+        let cumulative_percent_by_position = (0..max_len)
+            .map(|position| {
+                cumulative += self.start_counts.get(position).copied().unwrap_or(0);
+                cumulative as f64 / total_reads as f64 * 100.0
+            })
+            .collect();
+        AdapterContent {
+            name: self.name.clone(),
+            sequence: String::from_utf8_lossy(&self.seq).into_owned(),
+            cumulative_percent_by_position,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdapterContent {
+    pub name: String,
+    pub sequence: String,
+    /// Percentage of reads with the adapter starting at or before each position (1-based order)
+    pub cumulative_percent_by_position: Vec<f64>,
+}
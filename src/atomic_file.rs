@@ -0,0 +1,121 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Fails if `path` exists and may not be overwritten.
+pub fn check_clobber(path: &str, overwrite: bool) -> io::Result<()> {
+    if !overwrite && Path::new(path).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists, use --force to overwrite it", path),
+        ));
+    }
+    Ok(())
+}
+
+/// File written under a temporary name in the directory of its path, and renamed
+/// to its path by `commit`, so the path never holds a partially written file.
+/// The temporary file is removed if the `AtomicFile` is dropped without committing.
+pub struct AtomicFile {
+    file: File,
+    tmp_path: PathBuf,
+    path: String,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file, fails if `path` exists and `overwrite` is false.
+    pub fn create(path: &str, overwrite: bool) -> io::Result<Self> {
+        check_clobber(path, overwrite)?;
+        let file_name = Path::new(path)
+            .file_name()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a file path", path),
+                )
+            })?
+            .to_string_lossy();
+        let tmp_path =
+            Path::new(path).with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        Ok(AtomicFile {
+            file,
+            tmp_path,
+            path: path.to_string(),
+            committed: false,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Another handle to the temporary file, for writers that can not give back the file.
+    /// Everything written through it has to be flushed before `commit`.
+    pub fn try_clone_file(&self) -> io::Result<File> {
+        self.file.try_clone()
+    }
+
+    /// Syncs the file to disk and renames it to its path.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+        self.committed = true;
+        // Syncing the directory makes the rename durable, not every platform allows it
+        if let Some(dir) = Path::new(&self.path).parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Writes the content of an atomic file, written by `write`, and renames it into place.
+pub fn write_atomically(
+    file: AtomicFile,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_uncommitted(file, write)?.commit()?;
+    Ok(())
+}
+
+/// Writes the content of an atomic file, to be committed by the caller
+/// once the rest of the files of a set are written.
+pub fn write_uncommitted(
+    mut file: AtomicFile,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<AtomicFile, Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(&mut file);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    Ok(file)
+}
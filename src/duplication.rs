@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Upper count bound and label of each duplication level
+const DUPLICATION_LEVELS: [(usize, &str); 6] = [
+    (1, "1"),
+    (2, "2"),
+    (10, "3-10"),
+    (100, "11-100"),
+    (1000, "101-1000"),
+    (usize::MAX, ">1000"),
+];
+
+/// Bounded memory sample of the distinct sequences, a bottom-k sketch:
+/// keeps the `max_tracked` distinct sequences with the smallest hashes, with their exact counts.
+/// Whether a sequence is tracked only depends on its hash, so the sample does not depend on
+/// the read order and sketches of different parts of the input can be merged.
+#[derive(Default)]
+pub struct DuplicationSketch {
+    /// Set from the stats options, merging keeps the largest
+    pub max_tracked: usize,
+    counts: BTreeMap<u64, usize>,
+}
+
+#[derive(Serialize)]
+pub struct DuplicationLevel {
+    pub level: &'static str,
+    /// Distinct sequences seen this many times
+    pub sequences: usize,
+    /// Reads with those sequences
+    pub reads: usize,
+}
+
+#[derive(Serialize)]
+pub struct DuplicationStats {
+    /// Distinct sequences in the sample
+    pub tracked_sequences: usize,
+    /// Reads with a sampled sequence
+    pub tracked_reads: usize,
+    pub levels: Vec<DuplicationLevel>,
+    /// Estimated percentage of reads left after removing duplicates
+    pub percent_remaining_after_dedup: f64,
+}
+
+impl DuplicationSketch {
+    pub fn add(&mut self, seq: &[u8]) {
+        let hash = hash_seq(seq);
+        if let Some(count) = self.counts.get_mut(&hash) {
+            *count += 1;
+            return;
+        }
+        self.counts.insert(hash, 1);
+        self.evict_excess();
+    }
+
+    pub fn merge(&mut self, other: &DuplicationSketch) {
+        self.max_tracked = self.max_tracked.max(other.max_tracked);
+        for (hash, count) in &other.counts {
+            *self.counts.entry(*hash).or_insert(0) += count;
+        }
+        self.evict_excess();
+    }
+
+    /// Keeps only the sequences with the `max_tracked` smallest hashes.
+    /// An evicted sequence never gets back in, as only smaller hashes are added from then on.
+    fn evict_excess(&mut self) {
+        while self.counts.len() > self.max_tracked {
+            self.counts.pop_last();
+        }
+    }
+
+    pub fn summarize(&self) -> DuplicationStats {
+        let mut levels: Vec<DuplicationLevel> = DUPLICATION_LEVELS
+            .iter()
+            .map(|(_, level)| DuplicationLevel {
+                level,
+                sequences: 0,
+                reads: 0,
+            })
+            .collect();
+        for &count in self.counts.values() {
+            let level_idx = DUPLICATION_LEVELS
+                .iter()
+                .position(|(max_count, _)| count <= *max_count)
+                .unwrap();
+            levels[level_idx].sequences += 1;
+            levels[level_idx].reads += count;
+        }
+
+        let tracked_sequences = self.counts.len();
+        let tracked_reads: usize = self.counts.values().sum();
+        let percent_remaining_after_dedup = if tracked_reads > 0 {
+            tracked_sequences as f64 / tracked_reads as f64 * 100.0
+        } else {
+            100.0
+        };
+        DuplicationStats {
+            tracked_sequences,
+            tracked_reads,
+            levels,
+            percent_remaining_after_dedup,
+        }
+    }
+}
+
+/// 64-bit FNV-1a followed by the splitmix64 finalizer to spread the bits.
+/// Fixed, unlike the std hashers, so that sketches are reproducible across builds.
+fn hash_seq(seq: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in seq {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58476d1ce4e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
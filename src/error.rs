@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::seq_io::RecordPosition;
+
+/// Exit status for errors not covered by `ErrorKind`, like invalid option files
+pub const GENERIC_EXIT_CODE: i32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// Opening or reading an input file
+    Io,
+    /// Corrupt or truncated compressed input
+    Decompression,
+    /// Malformed FASTA/FASTQ records, or mates out of sync
+    Parse,
+    /// Writing the stats, the report or the sequences
+    Output,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Io => 4,
+            ErrorKind::Decompression => 5,
+            ErrorKind::Parse => 6,
+            ErrorKind::Output => 7,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Io => "I/O",
+            ErrorKind::Decompression => "decompression",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Output => "output",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Error with the file and, when reading, the record where it happened
+#[derive(Debug)]
+pub struct SeqStatsError {
+    pub kind: ErrorKind,
+    pub msg: String,
+    pub path: Option<String>,
+    pub position: Option<RecordPosition>,
+}
+
+impl SeqStatsError {
+    pub fn new(kind: ErrorKind, msg: impl Into<String>) -> Self {
+        SeqStatsError {
+            kind,
+            msg: msg.into(),
+            path: None,
+            position: None,
+        }
+    }
+
+    pub fn in_file(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn at(mut self, position: RecordPosition) -> Self {
+        self.position = Some(position);
+        self
+    }
+}
+
+impl fmt::Display for SeqStatsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error", self.kind)?;
+        if let Some(path) = &self.path {
+            write!(f, " in {}", path)?;
+        }
+        if let Some(position) = &self.position {
+            write!(
+                f,
+                " at record {} (byte offset {})",
+                position.record, position.byte_offset
+            )?;
+        }
+        write!(f, ": {}", self.msg)
+    }
+}
+
+impl Error for SeqStatsError {}
+
+/// Exit status for an error: the one of its kind, 4 for other I/O errors, or 1.
+pub fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    if let Some(error) = error.downcast_ref::<SeqStatsError>() {
+        error.kind.exit_code()
+    } else if error.is::<io::Error>() {
+        ErrorKind::Io.exit_code()
+    } else {
+        GENERIC_EXIT_CODE
+    }
+}
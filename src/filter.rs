@@ -0,0 +1,140 @@
+use regex::Regex;
+use serde::Serialize;
+
+use crate::seq_io::SeqRecord;
+
+pub struct FilterOptions {
+    pub min_len: Option<usize>,
+    pub max_len: Option<usize>,
+    /// Maximum fraction of N bases
+    pub max_n_fraction: Option<f64>,
+    /// Minimum mean Phred score, FASTA reads always pass
+    pub min_mean_qual: Option<f64>,
+    /// Minimum GC percentage
+    pub min_gc: Option<f64>,
+    /// Maximum GC percentage
+    pub max_gc: Option<f64>,
+    /// Reads whose ID does not match are removed
+    pub id_regex: Option<Regex>,
+    pub phred_offset: u8,
+    /// Use only unambiguous bases (A, C, G, T) as the GC percentage denominator
+    pub gc_unambiguous_only: bool,
+}
+
+/// Fragments removed by each filter.
+/// A fragment is removed if any of its reads fails a filter, and it is counted only once,
+/// under the first failing filter in the order of the fields.
+#[derive(Serialize, Default)]
+pub struct FilterStats {
+    pub fragments_passed: usize,
+    pub removed_by_min_len: usize,
+    pub removed_by_max_len: usize,
+    pub removed_by_max_n_fraction: usize,
+    pub removed_by_min_mean_qual: usize,
+    pub removed_by_gc_range: usize,
+    pub removed_by_id_regex: usize,
+}
+
+enum Filter {
+    MinLen,
+    MaxLen,
+    MaxNFraction,
+    MinMeanQual,
+    GcRange,
+    IdRegex,
+}
+
+pub struct ReadFilter {
+    options: FilterOptions,
+}
+
+impl ReadFilter {
+    pub fn new(options: FilterOptions) -> Self {
+        ReadFilter { options }
+    }
+
+    /// Checks every read of the fragment, returns true if the fragment passes.
+    pub fn check_fragment(&self, fragment: &[SeqRecord], stats: &mut FilterStats) -> bool {
+        match fragment
+            .iter()
+            .find_map(|record| self.failed_filter(record))
+        {
+            None => {
+                stats.fragments_passed += 1;
+                true
+            }
+            Some(filter) => {
+                let removed = match filter {
+                    Filter::MinLen => &mut stats.removed_by_min_len,
+                    Filter::MaxLen => &mut stats.removed_by_max_len,
+                    Filter::MaxNFraction => &mut stats.removed_by_max_n_fraction,
+                    Filter::MinMeanQual => &mut stats.removed_by_min_mean_qual,
+                    Filter::GcRange => &mut stats.removed_by_gc_range,
+                    Filter::IdRegex => &mut stats.removed_by_id_regex,
+                };
+                *removed += 1;
+                false
+            }
+        }
+    }
+
+    /// First filter failed by the read, if any.
+    fn failed_filter(&self, record: &SeqRecord) -> Option<Filter> {
+        let options = &self.options;
+        let seq = record.seq();
+        let len = seq.len();
+
+        if options.min_len.is_some_and(|min_len| len < min_len) {
+            return Some(Filter::MinLen);
+        }
+        if options.max_len.is_some_and(|max_len| len > max_len) {
+            return Some(Filter::MaxLen);
+        }
+        if let Some(max_n_fraction) = options.max_n_fraction {
+            let n_count = seq.iter().filter(|&&b| b == b'N' || b == b'n').count();
+            if len > 0 && n_count as f64 / len as f64 > max_n_fraction {
+                return Some(Filter::MaxNFraction);
+            }
+        }
+        if let (Some(min_mean_qual), Some(qual)) = (options.min_mean_qual, record.qual()) {
+            let qual_sum: usize = qual
+                .iter()
+                .map(|&q| q.saturating_sub(options.phred_offset) as usize)
+                .sum();
+            if qual_sum as f64 / (qual.len() as f64) < min_mean_qual {
+                return Some(Filter::MinMeanQual);
+            }
+        }
+        if options.min_gc.is_some() || options.max_gc.is_some() {
+            let gc_count = seq
+                .iter()
+                .filter(|&&b| matches!(b, b'G' | b'g' | b'C' | b'c'))
+                .count();
+            let gc_denominator = if options.gc_unambiguous_only {
+                seq.iter()
+                    .filter(|&&b| {
+                        matches!(b, b'A' | b'a' | b'C' | b'c' | b'G' | b'g' | b'T' | b't')
+                    })
+                    .count()
+            } else {
+                len
+            };
+            let gc_percent = if gc_denominator > 0 {
+                gc_count as f64 / gc_denominator as f64 * 100.0
+            } else {
+                0.0
+            };
+            if options.min_gc.is_some_and(|min_gc| gc_percent < min_gc)
+                || options.max_gc.is_some_and(|max_gc| gc_percent > max_gc)
+            {
+                return Some(Filter::GcRange);
+            }
+        }
+        if let Some(id_regex) = &options.id_regex {
+            if !id_regex.is_match(record.id()) {
+                return Some(Filter::IdRegex);
+            }
+        }
+        None
+    }
+}
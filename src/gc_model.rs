@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::qc::Status;
+
+/// Fraction of the reads expected for each GC percentage
+pub type ExpectedGc = BTreeMap<u8, f64>;
+
+/// FastQC limits of the GC deviation percentage
+const DEVIATION_WARN_PERCENT: f64 = 15.0;
+const DEVIATION_FAIL_PERCENT: f64 = 30.0;
+
+/// Observed GC distribution compared to an expected one
+#[derive(Serialize, Deserialize)]
+pub struct GcComparison {
+    /// Reads expected for each GC percentage, scaled to the number of reads observed
+    pub expected_distrib: BTreeMap<u8, f64>,
+    /// Sum of the absolute differences between the observed and expected reads,
+    /// as a percentage of the reads: 0 for identical distributions, up to 200.
+    pub deviation_percent: f64,
+    /// Warn above a deviation of 15% and fail above 30%, as FastQC does,
+    /// so that a distribution that is not the expected one, like a bimodal one, is flagged.
+    /// It ignores the `gc_deviation` limits of the QC thresholds file, checked in the QC status.
+    /// Computed again when stats files are merged
+    #[serde(default)]
+    pub fastqc_status: Status,
+}
+
+/// Normal distribution fitted to the mean and variance of the observed GC distribution
+#[derive(Serialize)]
+pub struct TheoreticalGc {
+    pub mean: f64,
+    pub sd: f64,
+    #[serde(flatten)]
+    pub comparison: GcComparison,
+}
+
+fn round_to_hundredths(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+pub fn compare_gc(gc_distrib: &BTreeMap<u8, usize>, expected: &ExpectedGc) -> GcComparison {
+    let total: usize = gc_distrib.values().sum();
+    let expected_distrib: BTreeMap<u8, f64> = expected
+        .iter()
+        .map(|(&gc, &fraction)| (gc, fraction * total as f64))
+        .collect();
+
+    let mut abs_diff_sum = 0.0;
+    for gc in 0..=100 {
+        let observed = gc_distrib.get(&gc).copied().unwrap_or(0) as f64;
+        let expected = expected_distrib.get(&gc).copied().unwrap_or(0.0);
+        abs_diff_sum += (observed - expected).abs();
+    }
+    let deviation_percent = if total > 0 {
+        abs_diff_sum / total as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    GcComparison {
+        expected_distrib: expected_distrib
+            .into_iter()
+            .map(|(gc, reads)| (gc, round_to_hundredths(reads)))
+            .collect(),
+        deviation_percent: round_to_hundredths(deviation_percent),
+        fastqc_status: deviation_status(deviation_percent),
+    }
+}
+
+fn deviation_status(deviation_percent: f64) -> Status {
+    if deviation_percent > DEVIATION_FAIL_PERCENT {
+        Status::Fail
+    } else if deviation_percent > DEVIATION_WARN_PERCENT {
+        Status::Warn
+    } else {
+        Status::Pass
+    }
+}
+
+/// Reads of at least this length can have almost any GC percentage with similar odds
+const MIN_LEN_EVEN_GC_PERCENTS: usize = 1000;
+
+/// Fits a normal distribution, restricted to the 0-100 range, to the observed GC distribution.
+/// Short reads can only have some GC percentages, e.g. even ones for 50 bp reads,
+/// and for 150 bp reads some percentages match two GC base counts and others one,
+/// so for reads shorter than 1000 bp the fitted distribution is spread over
+/// the GC base counts that their lengths allow, weighted by the length distribution.
+/// None if there are no reads.
+pub fn theoretical_gc(
+    gc_distrib: &BTreeMap<u8, usize>,
+    len_distrib: &BTreeMap<usize, usize>,
+) -> Option<TheoreticalGc> {
+    let total: usize = gc_distrib.values().sum();
+    if total == 0 {
+        return None;
+    }
+    let mean = gc_distrib
+        .iter()
+        .map(|(&gc, &count)| gc as f64 * count as f64)
+        .sum::<f64>()
+        / total as f64;
+    let variance = gc_distrib
+        .iter()
+        .map(|(&gc, &count)| (gc as f64 - mean).powi(2) * count as f64)
+        .sum::<f64>()
+        / total as f64;
+    let sd = variance.sqrt();
+
+    let mut expected: ExpectedGc = BTreeMap::new();
+    if sd > 0.0 {
+        let density = |gc: f64| (-0.5 * ((gc - mean) / sd).powi(2)).exp();
+        let num_reads: usize = len_distrib
+            .iter()
+            .filter(|(&len, _)| len > 0)
+            .map(|(_, &count)| count)
+            .sum();
+        let mut long_reads_fraction = 1.0;
+        for (&len, &count) in len_distrib.range(1..MIN_LEN_EVEN_GC_PERCENTS) {
+            // GC percentages of the possible GC base counts of a read of this length
+            let gc_percents: Vec<f64> = (0..=len)
+                .map(|gc_count| gc_count as f64 / len as f64 * 100.0)
+                .collect();
+            let density_sum: f64 = gc_percents.iter().map(|&gc| density(gc)).sum();
+            let len_fraction = count as f64 / num_reads as f64;
+            for gc in gc_percents {
+                *expected.entry(gc.round() as u8).or_insert(0.0) +=
+                    density(gc) / density_sum * len_fraction;
+            }
+            long_reads_fraction -= len_fraction;
+        }
+        if long_reads_fraction > 0.0 {
+            let density_sum: f64 = (0..=100).map(|gc| density(gc as f64)).sum();
+            for gc in 0..=100u8 {
+                *expected.entry(gc).or_insert(0.0) +=
+                    density(gc as f64) / density_sum * long_reads_fraction;
+            }
+        }
+    } else {
+        expected.insert(mean.round() as u8, 1.0);
+    }
+
+    Some(TheoreticalGc {
+        mean: round_to_hundredths(mean),
+        sd: round_to_hundredths(sd),
+        comparison: compare_gc(gc_distrib, &expected),
+    })
+}
+
+/// Reads an expected GC distribution, for instance of the reads of a reference genome,
+/// with a GC percentage and a read count or fraction per line, separated by whitespace.
+/// Lines starting with '#' and a first line header, like the one of the TSV output, are skipped.
+pub fn read_expected_gc(path: &str) -> Result<ExpectedGc, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut counts = BTreeMap::new();
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(gc), Some(count), None) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(format!(
+                "expected a GC percentage and a count in line {} of {}",
+                line_idx + 1,
+                path
+            )
+            .into());
+        };
+        let gc = match gc.parse::<u8>() {
+            Ok(gc) if gc <= 100 => gc,
+            Err(_) if counts.is_empty() && line_idx == 0 => continue,
+            _ => {
+                return Err(format!(
+                    "invalid GC percentage {} in line {} of {}, use an integer from 0 to 100",
+                    gc,
+                    line_idx + 1,
+                    path
+                )
+                .into())
+            }
+        };
+        let count = match count.parse::<f64>() {
+            Ok(count) if count >= 0.0 && count.is_finite() => count,
+            _ => {
+                return Err(format!(
+                    "invalid count {} in line {} of {}",
+                    count,
+                    line_idx + 1,
+                    path
+                )
+                .into())
+            }
+        };
+        *counts.entry(gc).or_insert(0.0) += count;
+    }
+
+    let total: f64 = counts.values().sum();
+    if total <= 0.0 {
+        return Err(format!("no reads in the expected GC distribution {}", path).into());
+    }
+    Ok(counts
+        .into_iter()
+        .map(|(gc, count)| (gc, count / total))
+        .collect())
+}
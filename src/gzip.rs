@@ -0,0 +1,459 @@
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Size of the chunks decompressed ahead by the plain gzip reader
+const READ_AHEAD_CHUNK_SIZE: usize = 1 << 20;
+
+/// Fixed part of the gzip header, up to and including XLEN
+const GZIP_HEADER_LEN: usize = 12;
+
+/// Checks whether a gzip stream starts with a BGZF block:
+/// a gzip member with the extra field holding a `BC` subfield with the block size.
+pub fn is_bgzf(buffer: &[u8]) -> bool {
+    buffer.len() >= 18
+        && buffer.starts_with(&[0x1f, 0x8b, 8])
+        && buffer[3] & 4 != 0
+        && buffer[12..14] == *b"BC"
+        && buffer[14..16] == [2, 0]
+}
+
+/// Decompressed data, with the range of compressed input bytes it was inflated from
+#[derive(Default)]
+struct Chunk {
+    data: Vec<u8>,
+    input_start: u64,
+    input_end: u64,
+}
+
+/// Decompressed data arrives in chunks, in input order
+enum ChunkSource {
+    /// Chunks produced by a single decompression thread
+    Sequential(Receiver<io::Result<Chunk>>),
+    /// One receiver per BGZF block, queued in input order and filled in by the worker threads
+    Blocks(Receiver<Receiver<io::Result<Chunk>>>),
+}
+
+/// Counts the bytes read from the compressed input
+struct CountingInput<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R: Read> Read for CountingInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.inner.read(buf)?;
+        self.bytes_read += num_bytes as u64;
+        Ok(num_bytes)
+    }
+}
+
+/// Reader of gzip data decompressed in background threads.
+/// The threads read the input well ahead of the data handed out, so the bytes of input
+/// consumed are tracked by the reader: it sets `consumed_input` to the input bytes
+/// behind the data read so far, interpolated within the current chunk.
+pub struct ThreadedGzReader {
+    source: ChunkSource,
+    chunk: Chunk,
+    pos: usize,
+    consumed_input: Arc<AtomicU64>,
+}
+
+impl ThreadedGzReader {
+    /// Decompresses a multi-member gzip stream in a background thread, so that
+    /// decompression overlaps with parsing.
+    /// The members of a plain gzip stream can not be located without inflating it,
+    /// so they can not be inflated in parallel.
+    pub fn read_ahead(input: impl Read + Send + 'static, consumed_input: Arc<AtomicU64>) -> Self {
+        let (sender, receiver) = sync_channel(4);
+        thread::spawn(move || {
+            let mut decoder = MultiGzDecoder::new(CountingInput {
+                inner: input,
+                bytes_read: 0,
+            });
+            let mut input_start = 0;
+            loop {
+                let mut data = Vec::with_capacity(READ_AHEAD_CHUNK_SIZE);
+                let result = (&mut decoder)
+                    .take(READ_AHEAD_CHUNK_SIZE as u64)
+                    .read_to_end(&mut data);
+                let done = !matches!(result, Ok(n) if n > 0);
+                let chunk = Chunk {
+                    data,
+                    input_start,
+                    input_end: decoder.get_ref().bytes_read,
+                };
+                input_start = chunk.input_end;
+                let sent = match result {
+                    // The data inflated before an error is passed on, so the error is located after it
+                    Err(error) if !chunk.data.is_empty() => {
+                        sender.send(Ok(chunk)).is_ok() && sender.send(Err(error)).is_ok()
+                    }
+                    result => sender.send(result.map(|_| chunk)).is_ok(),
+                };
+                if !sent || done {
+                    break;
+                }
+            }
+        });
+        ThreadedGzReader {
+            source: ChunkSource::Sequential(receiver),
+            chunk: Chunk::default(),
+            pos: 0,
+            consumed_input,
+        }
+    }
+
+    /// Inflates the blocks of a BGZF stream in parallel in `n_threads` worker threads.
+    pub fn bgzf(
+        input: impl Read + Send + 'static,
+        n_threads: usize,
+        consumed_input: Arc<AtomicU64>,
+    ) -> Self {
+        let (job_sender, job_receiver) =
+            sync_channel::<(Chunk, Sender<io::Result<Chunk>>)>(n_threads * 4);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        for _ in 0..n_threads {
+            let job_receiver = Arc::clone(&job_receiver);
+            thread::spawn(move || loop {
+                let job = job_receiver.lock().unwrap().recv();
+                let Ok((block, result_sender)) = job else {
+                    break;
+                };
+                let chunk = inflate_block(&block.data).map(|data| Chunk { data, ..block });
+                let _ = result_sender.send(chunk);
+            });
+        }
+
+        // Queueing the result receivers in input order keeps the output ordered,
+        // and the bounded queue limits the number of blocks in flight
+        let (block_sender, block_receiver) = sync_channel(n_threads * 4);
+        thread::spawn(move || {
+            let mut input = input;
+            let mut input_start = 0;
+            loop {
+                let (result_sender, result_receiver) = channel();
+                if block_sender.send(result_receiver).is_err() {
+                    break;
+                }
+                match read_bgzf_block(&mut input) {
+                    Ok(Some(block)) => {
+                        let input_end = input_start + block.len() as u64;
+                        let block = Chunk {
+                            data: block,
+                            input_start,
+                            input_end,
+                        };
+                        input_start = input_end;
+                        if job_sender.send((block, result_sender)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        let _ = result_sender.send(Ok(Chunk {
+                            data: Vec::new(),
+                            input_start,
+                            input_end: input_start,
+                        }));
+                        break;
+                    }
+                    Err(error) => {
+                        let _ = result_sender.send(Err(error));
+                        break;
+                    }
+                }
+            }
+        });
+        ThreadedGzReader {
+            source: ChunkSource::Blocks(block_receiver),
+            chunk: Chunk::default(),
+            pos: 0,
+            consumed_input,
+        }
+    }
+
+    /// Gets the next decompressed chunk, None at the end of the stream.
+    fn next_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        let next = match &self.source {
+            ChunkSource::Sequential(receiver) => receiver.recv().ok(),
+            ChunkSource::Blocks(receiver) => match receiver.recv() {
+                Ok(result_receiver) => Some(result_receiver.recv().map_err(|_| {
+                    io::Error::other("BGZF decompression thread stopped unexpectedly")
+                })?),
+                Err(_) => None,
+            },
+        };
+        next.transpose()
+    }
+}
+
+impl Read for ThreadedGzReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Empty chunks, like the BGZF end-of-file marker blocks of concatenated files, are skipped
+        while self.pos == self.chunk.data.len() {
+            match self.next_chunk()? {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => {
+                    self.consumed_input
+                        .store(self.chunk.input_end, Ordering::Relaxed);
+                    return Ok(0);
+                }
+            }
+        }
+        let chunk = &self.chunk;
+        let n = buf.len().min(chunk.data.len() - self.pos);
+        buf[..n].copy_from_slice(&chunk.data[self.pos..self.pos + n]);
+        self.pos += n;
+        let input_len = chunk.input_end - chunk.input_start;
+        self.consumed_input.store(
+            chunk.input_start + input_len * self.pos as u64 / chunk.data.len() as u64,
+            Ordering::Relaxed,
+        );
+        Ok(n)
+    }
+}
+
+/// Reads a whole BGZF block, None at the end of the input.
+fn read_bgzf_block(input: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; GZIP_HEADER_LEN];
+    let n = read_up_to(input, &mut header)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if n < GZIP_HEADER_LEN {
+        return Err(invalid_block("truncated block"));
+    }
+    if !header.starts_with(&[0x1f, 0x8b, 8]) || header[3] & 4 == 0 {
+        return Err(invalid_block("missing gzip header with extra field"));
+    }
+    let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+    let mut extra = vec![0u8; xlen];
+    input.read_exact(&mut extra).map_err(truncated_block)?;
+
+    let block_size = bgzf_block_size(&extra)
+        .ok_or_else(|| invalid_block("missing BC subfield in gzip extra field"))?;
+    if block_size < GZIP_HEADER_LEN + xlen {
+        return Err(invalid_block("block size smaller than its header"));
+    }
+
+    let mut block = Vec::with_capacity(block_size);
+    block.extend_from_slice(&header);
+    block.extend_from_slice(&extra);
+    block.resize(block_size, 0);
+    input
+        .read_exact(&mut block[GZIP_HEADER_LEN + xlen..])
+        .map_err(truncated_block)?;
+    Ok(Some(block))
+}
+
+/// Total block size stored in the BC subfield of the gzip extra field
+fn bgzf_block_size(extra: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let subfield_len = u16::from_le_bytes([extra[pos + 2], extra[pos + 3]]) as usize;
+        if extra[pos..pos + 2] == *b"BC" && subfield_len == 2 && pos + 6 <= extra.len() {
+            return Some(u16::from_le_bytes([extra[pos + 4], extra[pos + 5]]) as usize + 1);
+        }
+        pos += 4 + subfield_len;
+    }
+    None
+}
+
+fn inflate_block(block: &[u8]) -> io::Result<Vec<u8>> {
+    // The last 4 bytes of a gzip member hold the uncompressed size
+    let isize_bytes: [u8; 4] = block[block.len().saturating_sub(4)..]
+        .try_into()
+        .unwrap_or_default();
+    let mut data = Vec::with_capacity(u32::from_le_bytes(isize_bytes) as usize);
+    GzDecoder::new(block)
+        .read_to_end(&mut data)
+        .map_err(truncated_block)?;
+    Ok(data)
+}
+
+/// Like read_exact, but a clean end of input gives a short count instead of an error.
+fn read_up_to(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+/// The parser takes UnexpectedEof as a clean end of input, so truncation is reported as invalid data.
+fn truncated_block(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        invalid_block("truncated block")
+    } else {
+        error
+    }
+}
+
+fn invalid_block(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid BGZF block: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder};
+    use flate2::{Compression, Crc};
+    use std::io::{Cursor, Write};
+
+    /// A BGZF block holding `data`: a gzip member with the BC subfield in its extra field
+    fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let deflated = encoder.finish().unwrap();
+        let block_size = 18 + deflated.len() + 8;
+        let mut crc = Crc::new();
+        crc.update(data);
+
+        let mut block = vec![
+            0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+        ];
+        block.extend_from_slice(&((block_size - 1) as u16).to_le_bytes());
+        block.extend_from_slice(&deflated);
+        block.extend_from_slice(&crc.sum().to_le_bytes());
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        block
+    }
+
+    fn gzip_member(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn text(num_lines: usize) -> Vec<u8> {
+        (0..num_lines)
+            .flat_map(|idx| format!("@read{}\nACGTACGT\n+\nIIIIIIII\n", idx).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn bgzf_is_detected() {
+        assert!(is_bgzf(&bgzf_block(b"ACGT")));
+        assert!(!is_bgzf(&gzip_member(b"ACGT")));
+        assert!(!is_bgzf(b"@read\nACGT\n"));
+    }
+
+    #[test]
+    fn block_size_is_found_after_other_subfields() {
+        let extra = [b'X', b'Y', 1, 0, 7, b'B', b'C', 2, 0, 0x10, 0x00];
+        assert_eq!(bgzf_block_size(&extra), Some(0x11));
+        assert_eq!(bgzf_block_size(&extra[..5]), None);
+    }
+
+    #[test]
+    fn bgzf_blocks_are_inflated_in_order() {
+        let data = text(5000);
+        let mut stream = Vec::new();
+        for chunk in data.chunks(10_000) {
+            stream.extend(bgzf_block(chunk));
+        }
+        // End-of-file marker block
+        stream.extend(bgzf_block(b""));
+        for n_threads in [1, 3] {
+            let mut inflated = Vec::new();
+            ThreadedGzReader::bgzf(Cursor::new(stream.clone()), n_threads, Arc::default())
+                .read_to_end(&mut inflated)
+                .unwrap();
+            assert_eq!(inflated, data);
+        }
+    }
+
+    #[test]
+    fn truncated_bgzf_block_is_an_error() {
+        let data = text(2000);
+        let mut stream = Vec::new();
+        for chunk in data.chunks(10_000) {
+            stream.extend(bgzf_block(chunk));
+        }
+        for cut in [5, 30, 100] {
+            let truncated = stream[..stream.len() - cut].to_vec();
+            let mut inflated = Vec::new();
+            let error = ThreadedGzReader::bgzf(Cursor::new(truncated), 2, Arc::default())
+                .read_to_end(&mut inflated)
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), "Invalid BGZF block: truncated block");
+            // The blocks before the truncated one are read
+            assert!(data.starts_with(&inflated));
+            assert!(!inflated.is_empty());
+        }
+    }
+
+    #[test]
+    fn read_ahead_inflates_every_member() {
+        let data = text(40_000);
+        let (first, second) = data.split_at(data.len() / 3);
+        let mut stream = gzip_member(first);
+        stream.extend(gzip_member(second));
+        let mut inflated = Vec::new();
+        ThreadedGzReader::read_ahead(Cursor::new(stream), Arc::default())
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, data);
+    }
+
+    #[test]
+    fn consumed_input_follows_the_data_read() {
+        let data = text(40_000);
+        let mut bgzf_stream = Vec::new();
+        for chunk in data.chunks(50_000) {
+            bgzf_stream.extend(bgzf_block(chunk));
+        }
+        let streams = [(bgzf_stream, true), (gzip_member(&data), false)];
+        for (stream, is_bgzf) in streams {
+            let consumed_input = Arc::new(AtomicU64::new(0));
+            let input = Cursor::new(stream.clone());
+            let mut reader = if is_bgzf {
+                ThreadedGzReader::bgzf(input, 2, Arc::clone(&consumed_input))
+            } else {
+                ThreadedGzReader::read_ahead(input, Arc::clone(&consumed_input))
+            };
+            let mut half = vec![0; data.len() / 2];
+            reader.read_exact(&mut half).unwrap();
+            let consumed = consumed_input.load(Ordering::Relaxed) as f64;
+            let fraction = consumed / stream.len() as f64;
+            assert!((0.4..0.6).contains(&fraction), "{} consumed", fraction);
+
+            reader.read_to_end(&mut Vec::new()).unwrap();
+            assert_eq!(consumed_input.load(Ordering::Relaxed), stream.len() as u64);
+        }
+    }
+
+    #[test]
+    fn read_ahead_passes_on_the_data_before_a_truncation() {
+        let data = text(40_000);
+        let stream = gzip_member(&data);
+        let truncated = stream[..stream.len() / 2].to_vec();
+        let mut reader = ThreadedGzReader::read_ahead(Cursor::new(truncated), Arc::default());
+        let mut inflated = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => panic!("truncated stream read without error"),
+                Ok(n) => inflated.extend_from_slice(&buf[..n]),
+                Err(_) => break,
+            }
+        }
+        assert!(inflated.len() > data.len() / 4);
+        assert!(data.starts_with(&inflated));
+    }
+}
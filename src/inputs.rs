@@ -0,0 +1,465 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::{ErrorKind, SeqStatsError};
+use crate::paired::{Fragment, FragmentReader, PairReader};
+use crate::seq_io::{ReadProgress, SeqFormat, SeqReader};
+use crate::validate::{ValidateOptions, ValidationReport};
+
+/// An input: a file of single or interleaved reads, or the R1 and R2 files of read pairs
+#[derive(Clone, Debug)]
+pub struct InputFiles {
+    pub path: String,
+    pub r2_path: Option<String>,
+}
+
+/// Expands the glob patterns of the R1 and R2 inputs and pairs them in order.
+/// Patterns match in sorted order, so lanes like `_L001`..`_L004` pair up.
+pub fn expand_inputs(
+    patterns: &[String],
+    r2_patterns: &[String],
+) -> Result<Vec<InputFiles>, Box<dyn std::error::Error>> {
+    let paths = expand_globs(patterns)?;
+    if r2_patterns.is_empty() {
+        return Ok(paths
+            .into_iter()
+            .map(|path| InputFiles {
+                path,
+                r2_path: None,
+            })
+            .collect());
+    }
+    let r2_paths = expand_globs(r2_patterns)?;
+    if paths.len() != r2_paths.len() {
+        return Err(format!(
+            "{} R1 inputs but {} R2 inputs, each R1 input needs an R2 one",
+            paths.len(),
+            r2_paths.len()
+        )
+        .into());
+    }
+    Ok(paths
+        .into_iter()
+        .zip(r2_paths)
+        .map(|(path, r2_path)| InputFiles {
+            path,
+            r2_path: Some(r2_path),
+        })
+        .collect())
+}
+
+/// Reads a file of file names: an input path per line, or the R1 and R2 paths separated by a tab.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn read_input_list(list_fpath: &str) -> Result<Vec<InputFiles>, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(list_fpath)
+        .map_err(|err| SeqStatsError::new(ErrorKind::Io, err.to_string()).in_file(list_fpath))?;
+    let mut inputs = Vec::new();
+    for (line_idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let input = match fields[..] {
+            [path] => InputFiles {
+                path: path.to_string(),
+                r2_path: None,
+            },
+            [path, r2_path] => InputFiles {
+                path: path.to_string(),
+                r2_path: Some(r2_path.to_string()),
+            },
+            _ => {
+                return Err(format!(
+                    "Invalid line {} in {}: expected a path, or R1 and R2 paths separated by a tab",
+                    line_idx + 1,
+                    list_fpath
+                )
+                .into())
+            }
+        };
+        if inputs
+            .first()
+            .is_some_and(|first: &InputFiles| first.r2_path.is_some() != input.r2_path.is_some())
+        {
+            return Err(format!(
+                "Invalid line {} in {}: every line must have one path, or every line two",
+                line_idx + 1,
+                list_fpath
+            )
+            .into());
+        }
+        inputs.push(input);
+    }
+    if inputs.is_empty() {
+        return Err(format!("No input paths in {}", list_fpath).into());
+    }
+    Ok(inputs)
+}
+
+/// Paths matching each of the glob patterns, in the order of the patterns.
+pub fn expand_globs(patterns: &[String]) -> Result<Vec<String>, SeqStatsError> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        paths.extend(expand_glob(pattern)?);
+    }
+    Ok(paths)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Paths matching a pattern with `*`, `?` and `[...]` wildcards in any of its components,
+/// sorted. A path without wildcards is returned as is, even if it does not exist.
+fn expand_glob(pattern: &str) -> Result<Vec<String>, SeqStatsError> {
+    if !is_glob(pattern) {
+        return Ok(vec![pattern.to_string()]);
+    }
+    let (root, relative) = match pattern.strip_prefix('/') {
+        Some(relative) => ("/", relative),
+        None => ("", pattern),
+    };
+    let mut paths = vec![root.to_string()];
+    for component in relative
+        .split('/')
+        .filter(|component| !component.is_empty())
+    {
+        let mut next_paths = Vec::new();
+        for prefix in &paths {
+            if !is_glob(component) {
+                next_paths.push(join_path(prefix, component));
+                continue;
+            }
+            let dir = if prefix.is_empty() { "." } else { prefix };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                // Hidden files only match patterns that start with a dot, as in the shell
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| glob_match(component.as_bytes(), name.as_bytes()))
+                .collect();
+            names.sort();
+            next_paths.extend(names.iter().map(|name| join_path(prefix, name)));
+        }
+        paths = next_paths;
+    }
+    paths.retain(|path| Path::new(path).is_file());
+    if paths.is_empty() {
+        return Err(
+            SeqStatsError::new(ErrorKind::Io, "no file matches the pattern").in_file(pattern),
+        );
+    }
+    Ok(paths)
+}
+
+fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else if prefix.ends_with('/') {
+        format!("{}{}", prefix, name)
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Matches a file name against a pattern with `*`, `?` and `[...]` (or `[!...]`) wildcards.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|start| glob_match(rest, &name[start..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((b'[', rest)) => match (name.split_first(), class_end(rest)) {
+            (Some((&byte, name_rest)), Some(end)) => {
+                class_match(&rest[..end], byte) && glob_match(&rest[end + 1..], name_rest)
+            }
+            // Without a closing bracket the `[` is literal
+            (Some((&byte, name_rest)), None) => byte == b'[' && glob_match(rest, name_rest),
+            (None, _) => false,
+        },
+        Some((&literal, rest)) => name.first() == Some(&literal) && glob_match(rest, &name[1..]),
+    }
+}
+
+/// Position of the `]` closing a character class, a `]` right after the `[` is part of the class.
+fn class_end(class: &[u8]) -> Option<usize> {
+    let start = match class.first() {
+        Some(b'!' | b'^') => 1,
+        _ => 0,
+    };
+    class
+        .iter()
+        .skip(start + 1)
+        .position(|&byte| byte == b']')
+        .map(|pos| pos + start + 1)
+}
+
+fn class_match(class: &[u8], byte: u8) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some((b'!' | b'^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut idx = 0;
+    while idx < class.len() {
+        if idx + 2 < class.len() && class[idx + 1] == b'-' {
+            matched |= (class[idx]..=class[idx + 2]).contains(&byte);
+            idx += 3;
+        } else {
+            matched |= class[idx] == byte;
+            idx += 1;
+        }
+    }
+    matched != negated
+}
+
+/// Reads the fragments of several inputs, one input after the other.
+/// Each input is opened once the previous one is read, so only its decompression
+/// threads run.
+pub struct MultiFragmentReader {
+    inputs: Vec<InputFiles>,
+    interleaved: bool,
+    validation: Option<ValidateOptions>,
+    n_threads: usize,
+    /// Progress of the reading of each file of each input
+    progress: Vec<Vec<ReadProgress>>,
+    reader: FragmentReader,
+    current: usize,
+    /// Fragments read from the current input
+    num_read_in_input: usize,
+    /// Validation issues of the inputs already read
+    read_inputs_validation: Option<ValidationReport>,
+}
+
+impl MultiFragmentReader {
+    /// Opens the first input, the rest are opened as they are reached and must hold
+    /// the same format.
+    /// Also returns the progress of the reading of every input file.
+    pub fn open(
+        inputs: Vec<InputFiles>,
+        interleaved: bool,
+        validation: Option<&ValidateOptions>,
+        n_threads: usize,
+    ) -> Result<(Self, Vec<ReadProgress>), SeqStatsError> {
+        let progress = inputs
+            .iter()
+            .map(|input| {
+                [Some(&input.path), input.r2_path.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .map(|path| ReadProgress::new(path))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let reader =
+            open_fragment_reader(&inputs[0], interleaved, validation, n_threads, &progress[0])?;
+        let all_progress = progress.iter().flatten().cloned().collect();
+        let reader = MultiFragmentReader {
+            inputs,
+            interleaved,
+            validation: validation.cloned(),
+            n_threads,
+            progress,
+            reader,
+            current: 0,
+            num_read_in_input: 0,
+            read_inputs_validation: None,
+        };
+        Ok((reader, all_progress))
+    }
+
+    pub fn inputs(&self) -> &[InputFiles] {
+        &self.inputs
+    }
+
+    pub fn format(&self) -> SeqFormat {
+        self.reader.format()
+    }
+
+    pub fn num_mates(&self) -> usize {
+        self.reader.num_mates()
+    }
+
+    pub fn new_fragment(&self) -> Fragment {
+        self.reader.new_fragment()
+    }
+
+    /// Path of the file of each mate, for each input
+    pub fn mate_paths(&self) -> Vec<Vec<String>> {
+        self.inputs
+            .iter()
+            .map(|input| match &input.r2_path {
+                Some(r2_path) => vec![input.path.clone(), r2_path.clone()],
+                None if self.interleaved => vec![input.path.clone(); 2],
+                None => vec![input.path.clone()],
+            })
+            .collect()
+    }
+
+    /// Reads the next fragment into `fragment`, moving on to the next input at the end of one,
+    /// and records the input it came from.
+    /// Returns false at the end of the last input.
+    pub fn read(&mut self, fragment: &mut Fragment) -> Result<bool, SeqStatsError> {
+        while self.current < self.inputs.len() {
+            if self.reader.read(fragment, self.num_read_in_input + 1)? {
+                self.num_read_in_input += 1;
+                fragment.input = self.current;
+                return Ok(true);
+            }
+            self.current += 1;
+            self.num_read_in_input = 0;
+            if self.current < self.inputs.len() {
+                let next_reader = self.open_input(self.current)?;
+                let read_reader = std::mem::replace(&mut self.reader, next_reader);
+                if let Some(report) = read_reader.validation_report() {
+                    match &mut self.read_inputs_validation {
+                        Some(read_inputs_validation) => read_inputs_validation.merge(&report),
+                        None => self.read_inputs_validation = Some(report),
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn open_input(&self, input_idx: usize) -> Result<FragmentReader, SeqStatsError> {
+        let input = &self.inputs[input_idx];
+        let reader = open_fragment_reader(
+            input,
+            self.interleaved,
+            self.validation.as_ref(),
+            self.n_threads,
+            &self.progress[input_idx],
+        )?;
+        if reader.format() != self.reader.format() {
+            return Err(SeqStatsError::new(
+                ErrorKind::Parse,
+                format!(
+                    "input formats differ: {:?} vs {:?} in {}",
+                    reader.format(),
+                    self.reader.format(),
+                    self.inputs[0].path
+                ),
+            )
+            .in_file(&input.path));
+        }
+        Ok(reader)
+    }
+
+    /// Issues found by the validation in every input file read so far
+    pub fn validation_report(&self) -> Option<ValidationReport> {
+        let mut reports = [
+            self.read_inputs_validation.clone(),
+            self.reader.validation_report(),
+        ]
+        .into_iter()
+        .flatten();
+        let mut report = reports.next()?;
+        reports.for_each(|other| report.merge(&other));
+        Some(report)
+    }
+}
+
+/// Opens an input as single reads or, with an R2 file or interleaved input, as read pairs.
+/// `progress` holds the progress of the reading of each of its files.
+fn open_fragment_reader(
+    input: &InputFiles,
+    interleaved: bool,
+    validation: Option<&ValidateOptions>,
+    n_threads: usize,
+    progress: &[ReadProgress],
+) -> Result<FragmentReader, SeqStatsError> {
+    let reader = SeqReader::open(&input.path, n_threads, validation, &progress[0])?;
+    Ok(match &input.r2_path {
+        Some(in_r2_fpath) => {
+            let r2_reader = SeqReader::open(in_r2_fpath, n_threads, validation, &progress[1])?;
+            FragmentReader::Paired(PairReader::from_two_files(reader, r2_reader)?)
+        }
+        None if interleaved => FragmentReader::Paired(PairReader::Interleaved(reader)),
+        None => FragmentReader::Single(reader),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+
+    #[test]
+    fn star_matches_any_run_of_characters() {
+        assert!(matches("*.fq", "a.fq"));
+        assert!(matches("*.fq", ".fq"));
+        assert!(matches("S_*_R1*.fq.gz", "S_L001_R1_001.fq.gz"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.fq", "a.fq.gz"));
+        assert!(!matches("*_R1*", "S_R2.fq"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("L00?.fq", "L001.fq"));
+        assert!(!matches("L00?.fq", "L00.fq"));
+        assert!(!matches("L00?.fq", "L0012.fq"));
+    }
+
+    #[test]
+    fn classes_match_listed_characters_and_ranges() {
+        assert!(matches("L00[12].fq", "L002.fq"));
+        assert!(!matches("L00[12].fq", "L003.fq"));
+        assert!(matches("L00[1-3].fq", "L003.fq"));
+        assert!(!matches("L00[1-3].fq", "L004.fq"));
+        assert!(matches("[a-cx]", "x"));
+        assert!(matches("L00[!1].fq", "L002.fq"));
+        assert!(!matches("L00[!1].fq", "L001.fq"));
+        assert!(!matches("L00[!0-9].fq", "L005.fq"));
+        assert!(matches("L00[^0-9].fq", "L00x.fq"));
+        // A `]` right after the `[` is part of the class
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[!]]", "a"));
+        assert!(!matches("[!]]", "]"));
+        // Without a closing bracket the `[` is literal
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+    }
+
+    #[test]
+    fn expanded_paths_are_sorted_and_skip_hidden_files() {
+        let dir = std::env::temp_dir().join(format!("seq_stats_glob_{}", std::process::id()));
+        let lanes_dir = dir.join("lanes");
+        fs::create_dir_all(&lanes_dir).unwrap();
+        for name in [
+            "S_L002.fq",
+            "S_L010.fq",
+            "S_L001.fq",
+            ".S_L003.fq",
+            "other.fq",
+        ] {
+            fs::write(lanes_dir.join(name), "").unwrap();
+        }
+        // Directories are not inputs
+        fs::create_dir_all(lanes_dir.join("S_L004.fq")).unwrap();
+        let dir_path = dir.to_string_lossy();
+
+        let paths = expand_glob(&format!("{}/lan*/S_L0*.fq", dir_path)).unwrap();
+        let expected: Vec<String> = ["S_L001.fq", "S_L002.fq", "S_L010.fq"]
+            .iter()
+            .map(|name| format!("{}/lanes/{}", dir_path, name))
+            .collect();
+        assert_eq!(paths, expected);
+
+        let hidden = expand_glob(&format!("{}/lanes/.S_*", dir_path)).unwrap();
+        assert_eq!(hidden, [format!("{}/lanes/.S_L003.fq", dir_path)]);
+
+        assert!(expand_glob(&format!("{}/lanes/*.fa", dir_path)).is_err());
+        // Paths without wildcards are kept, even if they do not exist
+        let missing = format!("{}/missing.fq", dir_path);
+        assert_eq!(expand_glob(&missing).unwrap(), vec![missing]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
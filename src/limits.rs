@@ -0,0 +1,82 @@
+use serde::Serialize;
+
+use crate::seq_io::ReadProgress;
+
+/// Stops the reading after a number of fragments or of bytes read from the input files
+pub struct ReadLimits {
+    max_fragments: Option<usize>,
+    max_bytes: Option<u64>,
+    progress: Vec<ReadProgress>,
+    stop_reason: Option<&'static str>,
+}
+
+/// Reported when the reading stopped before the end of the input
+#[derive(Serialize)]
+pub struct PartialStats {
+    pub stop_reason: &'static str,
+    pub fragments_read: usize,
+    /// Bytes read from the input files, before decompression
+    pub bytes_read: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_bytes: Option<u64>,
+    /// Fragments read extrapolated to the whole input by the fraction of its bytes read,
+    /// not available for stdin.
+    /// It is an underestimate, by the input read ahead of the parsed fragments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_total_fragments: Option<usize>,
+}
+
+impl ReadLimits {
+    pub fn new(
+        max_fragments: Option<usize>,
+        max_bytes: Option<u64>,
+        progress: Vec<ReadProgress>,
+    ) -> Self {
+        ReadLimits {
+            max_fragments,
+            max_bytes,
+            progress,
+            stop_reason: None,
+        }
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.progress.iter().map(ReadProgress::bytes_read).sum()
+    }
+
+    /// Checks if a limit has been reached before reading the next fragment.
+    /// The input is read ahead in blocks, so the byte limit is only checked
+    /// once some fragment has been read.
+    pub fn reached(&mut self, num_fragments: usize) -> bool {
+        if self.max_fragments.is_some_and(|max| num_fragments >= max) {
+            self.stop_reason = Some("max_reads");
+        } else if num_fragments > 0 && self.max_bytes.is_some_and(|max| self.bytes_read() >= max) {
+            self.stop_reason = Some("max_bytes");
+        }
+        self.stop_reason.is_some()
+    }
+
+    /// None if the whole input was read
+    pub fn partial_stats(&self, num_fragments: usize) -> Option<PartialStats> {
+        let stop_reason = self.stop_reason?;
+        let bytes_read = self.bytes_read();
+        let input_bytes = self
+            .progress
+            .iter()
+            .map(ReadProgress::file_size)
+            .sum::<Option<u64>>();
+        let estimated_total_fragments = match input_bytes {
+            Some(input_bytes) if bytes_read > 0 => Some(
+                (num_fragments as f64 * input_bytes as f64 / bytes_read as f64).round() as usize,
+            ),
+            _ => None,
+        };
+        Some(PartialStats {
+            stop_reason,
+            fragments_read: num_fragments,
+            bytes_read,
+            input_bytes,
+            estimated_total_fragments,
+        })
+    }
+}
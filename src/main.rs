@@ -1,149 +1,1027 @@
-use bio::io::fastq::{Error as FastqError, FastqRead, Reader, Record, Writer};
-use clap::Parser;
-use flate2::read::MultiGzDecoder;
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand};
 use serde::Serialize;
-use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read};
+use std::io;
 
-/// Opens a file or stdin, and decompresses if gzipped.
-/// `"-"` means read from stdin.
-fn open_maybe_gzipped(path: &str) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
-    let raw_input: Box<dyn Read> = if path == "-" {
-        Box::new(io::stdin().lock())
-    } else {
-        Box::new(File::open(path)?)
-    };
+mod adapters;
+mod atomic_file;
+mod duplication;
+mod error;
+mod filter;
+mod gc_model;
+mod gzip;
+mod inputs;
+mod limits;
+mod merge;
+mod output;
+mod overrepresented;
+mod paired;
+mod parallel;
+mod qc;
+mod report;
+mod sample;
+mod seq_io;
+mod stats;
+mod trim;
+mod validate;
 
-    let mut buf_reader = BufReader::new(raw_input);
+use atomic_file::AtomicFile;
+use error::{ErrorKind, SeqStatsError};
+use filter::{FilterOptions, FilterStats, ReadFilter};
+use inputs::{InputFiles, MultiFragmentReader};
+use limits::{PartialStats, ReadLimits};
+use output::{OutputFormat, StatsOutput};
+use overrepresented::OverrepTracker;
+use paired::Fragment;
+use parallel::BatchProcessor;
+use qc::{QcStatus, QcThresholds, Status};
+use sample::{FractionSampler, ReservoirSampler, SamplingMethod, SamplingOptions, SamplingStats};
+use seq_io::{RecordPosition, SeqRecord, SeqWriter};
+use stats::{FragmentStats, ReadStats, StatsOptions};
+use trim::{TrimOptions, TrimStats, Trimmer};
+use validate::{DupIdCheck, ValidateOptions, ValidationReport};
 
-    // Use fill_buf to peek at the buffer without consuming bytes
-    let buffer = buf_reader.fill_buf()?;
-    let is_gzipped = buffer.starts_with(&[0x1f, 0x8b]);
+/// Adds a record to the stats, locating the record in its file if it is rejected.
+fn add_record_to_stats(
+    stats: &mut ReadStats,
+    record: &SeqRecord,
+    path: &str,
+    position: RecordPosition,
+    options: &StatsOptions,
+) -> Result<(), SeqStatsError> {
+    stats
+        .add_read(record.seq(), record.qual(), options)
+        .map_err(|msg| {
+            SeqStatsError::new(ErrorKind::Parse, format!("{} ({})", msg, record.id()))
+                .in_file(path)
+                .at(position)
+        })
+}
 
-    // Now wrap in gzip decoder or not, preserving buffer
-    if is_gzipped {
-        Ok(Box::new(MultiGzDecoder::new(buf_reader)))
-    } else {
-        Ok(Box::new(buf_reader))
+/// Adds each read of a fragment to the stats of its mate.
+/// `positions` and `mate_paths` locate the reads in the input files.
+fn add_fragment_to_stats(
+    mate_stats: &mut [ReadStats],
+    records: &[SeqRecord],
+    positions: &[RecordPosition],
+    mate_paths: &[String],
+    options: &StatsOptions,
+) -> Result<(), SeqStatsError> {
+    for (((stats, record), position), path) in mate_stats
+        .iter_mut()
+        .zip(records)
+        .zip(positions)
+        .zip(mate_paths)
+    {
+        add_record_to_stats(stats, record, path, *position, options)?;
     }
+    Ok(())
+}
+
+/// Error writing an output file, or stdout
+fn output_error(path: &str, error: impl std::fmt::Display) -> SeqStatsError {
+    SeqStatsError::new(ErrorKind::Output, error.to_string()).in_file(path)
 }
 
+fn new_mate_stats(num_mates: usize) -> Vec<ReadStats> {
+    (0..num_mates).map(|_| ReadStats::new()).collect()
+}
+
+fn merge_mate_stats(mate_stats: &mut [ReadStats], other: &[ReadStats]) {
+    for (stats, other_stats) in mate_stats.iter_mut().zip(other) {
+        stats.merge(other_stats);
+    }
+}
+
+/// Hands the overrepresented sequence trackers to the mate stats and finishes them.
+fn finish_mate_stats(
+    mut mate_stats: Vec<ReadStats>,
+    overrep_trackers: Vec<OverrepTracker>,
+) -> FragmentStats {
+    for (stats, tracker) in mate_stats.iter_mut().zip(overrep_trackers) {
+        stats.overrep_tracker = tracker;
+    }
+    FragmentStats::from_mates(mate_stats)
+}
+
+/// Stats of the input reads, followed by the trimming, filtering, sampling, partial input and QC sections when enabled
 #[derive(Serialize)]
-struct ReadStats {
-    total_records: i32,
-    gc_distrib: HashMap<u8, usize>,
-    len_distrib: HashMap<usize, usize>,
+struct StatsReport {
+    #[serde(flatten)]
+    stats: FragmentStats,
+    /// Stats of each input when there are several, the top level ones are their total
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    per_input: Vec<InputStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after_trimming: Option<FragmentStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trimming: Option<TrimStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filtering: Option<FilterStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sampling: Option<SamplingStats>,
+    /// Present when the reading stopped before the end of the input
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partial: Option<PartialStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qc_status: Option<QcStatus>,
+    /// Present with --validate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<ValidationReport>,
 }
 
-fn calc_read_stats(
-    in_seq_fpath: &str,
-    out_stats_fpath: &str,
-    out_seqs: &bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let input = open_maybe_gzipped(in_seq_fpath)?;
-
-    let bufreader = BufReader::new(input);
-    let mut reader = Reader::new(bufreader);
-
-    let mut record = Record::new();
-    let mut total_records: i32 = 0;
-    let mut gc_count: usize;
-    let mut gc_percent: u8;
-    let mut len: usize;
-    let mut seq: &[u8];
-    let mut gc_distrib: HashMap<u8, usize> = HashMap::new();
-    let mut len_distrib: HashMap<usize, usize> = HashMap::new();
-
-    let handle = io::stdout().lock();
-    let mut fastq_writer = Writer::new(handle);
-
-    loop {
-        // Try to read the next record
-        let read_result = reader.read(&mut record);
-
-        // Handle possible errors
-        if let Err(e) = read_result {
-            match e {
-                FastqError::ReadError(ref io_err) if io_err.kind() == ErrorKind::UnexpectedEof => {
-                    break
+/// Stats of the reads of one input
+#[derive(Serialize)]
+struct InputStats {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r2_path: Option<String>,
+    #[serde(flatten)]
+    stats: FragmentStats,
+}
+
+/// A fragment as handed to the stats workers
+struct StatsItem {
+    fragment: Fragment,
+    /// Add the fragment to the input stats
+    add_to_input_stats: bool,
+    /// Fragment after trimming, None without a trimmer or if it was discarded
+    trimmed_fragment: Option<Vec<SeqRecord>>,
+}
+
+/// Steps run on each fragment in input order: trimming, stats, filtering and output.
+struct FragmentPipeline<'a> {
+    /// Accumulates the input stats of each input, and the stats after trimming
+    processor: BatchProcessor<StatsItem, (Vec<Vec<ReadStats>>, Vec<ReadStats>)>,
+    raw_overrep_trackers: Vec<OverrepTracker>,
+    /// Trackers of each input, only with several inputs
+    input_overrep_trackers: Vec<Vec<OverrepTracker>>,
+    /// Inputs reported one by one, only when there are several
+    inputs: Vec<InputFiles>,
+    trimmed_overrep_trackers: Vec<OverrepTracker>,
+    trimmer: Option<&'a Trimmer>,
+    trim_stats: TrimStats,
+    filter: Option<&'a ReadFilter>,
+    filter_stats: FilterStats,
+    seq_writer: Option<SeqWriter<io::StdoutLock<'static>>>,
+    /// Writer of the fragments removed by the filter, with its file, renamed into place by `finish`
+    rejected_writer: Option<(SeqWriter<File>, AtomicFile)>,
+}
+
+impl<'a> FragmentPipeline<'a> {
+    fn new(
+        reader: &MultiFragmentReader,
+        out_seqs: bool,
+        options: &StatsOptions,
+        trimmer: Option<&'a Trimmer>,
+        filter: Option<&'a ReadFilter>,
+        rejected_file: Option<AtomicFile>,
+        n_threads: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let num_mates = reader.num_mates();
+        let num_inputs = reader.inputs().len();
+        let worker_options = options.clone();
+        let mate_paths = reader.mate_paths();
+        let processor = BatchProcessor::new(
+            n_threads,
+            || {
+                let raw_stats = (0..num_inputs).map(|_| new_mate_stats(num_mates)).collect();
+                (raw_stats, new_mate_stats(num_mates))
+            },
+            move |(raw_stats, trimmed_stats): &mut (Vec<Vec<ReadStats>>, _),
+                  _,
+                  item: &StatsItem| {
+                let input = item.fragment.input;
+                let positions = &item.fragment.positions;
+                if item.add_to_input_stats {
+                    add_fragment_to_stats(
+                        &mut raw_stats[input],
+                        &item.fragment.records,
+                        positions,
+                        &mate_paths[input],
+                        &worker_options,
+                    )?;
                 }
-                FastqError::IncompleteRecord => {
-                    return Err("Encountered incomplete FASTQ record".into())
+                if let Some(trimmed_fragment) = &item.trimmed_fragment {
+                    add_fragment_to_stats(
+                        trimmed_stats,
+                        trimmed_fragment,
+                        positions,
+                        &mate_paths[input],
+                        &worker_options,
+                    )?;
                 }
-                other => return Err(Box::new(other)),
+                Ok(())
+            },
+        );
+
+        let new_overrep_trackers = || -> Vec<OverrepTracker> {
+            (0..num_mates)
+                .map(|_| {
+                    OverrepTracker::new(options.overrep_min_fraction, options.overrep_prefix_len)
+                })
+                .collect()
+        };
+        let rejected_writer = match rejected_file {
+            Some(rejected_file) => {
+                let file = rejected_file
+                    .try_clone_file()
+                    .map_err(|err| output_error(rejected_file.path(), err))?;
+                Some((SeqWriter::new(reader.format(), file), rejected_file))
+            }
+            None => None,
+        };
+
+        Ok(FragmentPipeline {
+            processor,
+            raw_overrep_trackers: new_overrep_trackers(),
+            input_overrep_trackers: if num_inputs > 1 {
+                (0..num_inputs).map(|_| new_overrep_trackers()).collect()
+            } else {
+                Vec::new()
+            },
+            inputs: if num_inputs > 1 {
+                reader.inputs().to_vec()
+            } else {
+                Vec::new()
+            },
+            trimmed_overrep_trackers: new_overrep_trackers(),
+            trimmer,
+            trim_stats: TrimStats::default(),
+            filter,
+            filter_stats: FilterStats::default(),
+            seq_writer: out_seqs.then(|| SeqWriter::new(reader.format(), io::stdout().lock())),
+            rejected_writer,
+        })
+    }
+
+    /// Adds a fragment to the input stats only, for fragments left out of the sample.
+    fn add_to_input_stats(&mut self, fragment: Fragment) -> Result<(), Box<dyn std::error::Error>> {
+        self.track_input_seqs(&fragment);
+        self.processor.add(StatsItem {
+            fragment,
+            add_to_input_stats: true,
+            trimmed_fragment: None,
+        })
+    }
+
+    /// Feeds the overrepresented sequence trackers of the input stats.
+    fn track_input_seqs(&mut self, fragment: &Fragment) {
+        for (tracker, record) in self.raw_overrep_trackers.iter_mut().zip(&fragment.records) {
+            tracker.add(record.seq());
+        }
+        if let Some(trackers) = self.input_overrep_trackers.get_mut(fragment.input) {
+            for (tracker, record) in trackers.iter_mut().zip(&fragment.records) {
+                tracker.add(record.seq());
             }
         }
+    }
+
+    /// Trims, filters and writes a fragment, adding it to the stats.
+    fn process(
+        &mut self,
+        fragment: Fragment,
+        add_to_input_stats: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let records = &fragment.records;
+        let trimmed_fragment = self
+            .trimmer
+            .and_then(|trimmer| trimmer.trim_fragment(records, &mut self.trim_stats));
 
-        // Check for implicit EOF case (blank trailing lines)
-        if record.seq().is_empty() {
-            break;
+        if add_to_input_stats {
+            self.track_input_seqs(&fragment);
+        }
+        if let Some(trimmed_fragment) = &trimmed_fragment {
+            for (tracker, record) in self
+                .trimmed_overrep_trackers
+                .iter_mut()
+                .zip(trimmed_fragment)
+            {
+                tracker.add(record.seq());
+            }
         }
 
-        seq = record.seq();
-        gc_count = seq
-            .iter()
-            .filter(|&&b| b == b'G' || b == b'g' || b == b'C' || b == b'c')
-            .count();
-        len = seq.len();
-        gc_percent = ((gc_count as f64 / len as f64) * 100.0).round() as u8;
-        *gc_distrib.entry(gc_percent).or_insert(0) += 1;
-        *len_distrib.entry(len).or_insert(0) += 1;
-
-        if *out_seqs {
-            fastq_writer.write_record(&record)?;
+        let out_fragment = match self.trimmer {
+            Some(_) => trimmed_fragment.as_deref(),
+            None => Some(records.as_slice()),
         };
-        total_records += 1;
+        if let Some(out_fragment) = out_fragment {
+            self.write_fragment(out_fragment)?;
+        }
+
+        self.processor.add(StatsItem {
+            fragment,
+            add_to_input_stats,
+            trimmed_fragment,
+        })
     }
 
-    let stats = ReadStats {
-        total_records: total_records,
-        gc_distrib: gc_distrib,
-        len_distrib: len_distrib,
-    };
+    /// Writes a fragment to stdout if it passes the filter, otherwise to the rejected file.
+    fn write_fragment(&mut self, fragment: &[SeqRecord]) -> Result<(), Box<dyn std::error::Error>> {
+        let passed = self
+            .filter
+            .map(|filter| filter.check_fragment(fragment, &mut self.filter_stats))
+            .unwrap_or(true);
+        if passed {
+            if let Some(seq_writer) = self.seq_writer.as_mut() {
+                seq_writer
+                    .write_records(fragment)
+                    .map_err(|err| output_error("stdout", err))?;
+            }
+        } else if let Some((rejected_writer, rejected_file)) = self.rejected_writer.as_mut() {
+            rejected_writer
+                .write_records(fragment)
+                .map_err(|err| output_error(rejected_file.path(), err))?;
+        }
+        Ok(())
+    }
 
-    // write stats to file
-    let file = File::create(out_stats_fpath)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, &stats)?;
+    /// Waits for the stats workers and builds the report.
+    fn finish(mut self) -> Result<StatsReport, Box<dyn std::error::Error>> {
+        if let Some((mut rejected_writer, rejected_file)) = self.rejected_writer.take() {
+            let path = rejected_file.path().to_string();
+            rejected_writer
+                .flush()
+                .and_then(|_| rejected_file.commit())
+                .map_err(|err| output_error(&path, err))?;
+        }
 
-    Ok(())
+        let num_mates = self.raw_overrep_trackers.len();
+        let mut input_stats: Vec<Vec<ReadStats>> = Vec::new();
+        let mut trimmed_stats = new_mate_stats(num_mates);
+        for (partial_raw_stats, partial_trimmed_stats) in self.processor.finish()? {
+            if input_stats.is_empty() {
+                input_stats = partial_raw_stats;
+            } else {
+                for (stats, partial_stats) in input_stats.iter_mut().zip(&partial_raw_stats) {
+                    merge_mate_stats(stats, partial_stats);
+                }
+            }
+            merge_mate_stats(&mut trimmed_stats, &partial_trimmed_stats);
+        }
+        let mut raw_stats = new_mate_stats(num_mates);
+        for stats in &input_stats {
+            merge_mate_stats(&mut raw_stats, stats);
+        }
+        let per_input = self
+            .inputs
+            .into_iter()
+            .zip(input_stats)
+            .zip(self.input_overrep_trackers)
+            .map(|((input, stats), trackers)| InputStats {
+                path: input.path,
+                r2_path: input.r2_path,
+                stats: finish_mate_stats(stats, trackers),
+            })
+            .collect();
+
+        let trimmed_overrep_trackers = self.trimmed_overrep_trackers;
+        Ok(StatsReport {
+            stats: finish_mate_stats(raw_stats, self.raw_overrep_trackers),
+            per_input,
+            after_trimming: self
+                .trimmer
+                .map(|_| finish_mate_stats(trimmed_stats, trimmed_overrep_trackers)),
+            trimming: self.trimmer.map(|_| self.trim_stats),
+            filtering: self.filter.map(|_| self.filter_stats),
+            sampling: None,
+            partial: None,
+            qc_status: None,
+            validation: None,
+        })
+    }
+}
+
+/// Calculates the stats of single reads or read pairs.
+/// Pairs are written interleaved to stdout.
+/// With a trimmer the stats are calculated before and after trimming,
+/// and only the trimmed reads are written.
+/// With a filter only the fragments that pass it are written,
+/// the rejected ones go to `rejected_file` if given, renamed into place at the end.
+/// With sampling only the sampled fragments are trimmed, filtered and written.
+/// The reading stops early once `limits` are reached.
+#[allow(clippy::too_many_arguments)]
+fn calc_read_stats(
+    reader: &mut MultiFragmentReader,
+    mut limits: ReadLimits,
+    out_seqs: &bool,
+    options: &StatsOptions,
+    trimmer: Option<&Trimmer>,
+    filter: Option<&ReadFilter>,
+    rejected_file: Option<AtomicFile>,
+    sampling: Option<&SamplingOptions>,
+    n_threads: usize,
+) -> Result<StatsReport, Box<dyn std::error::Error>> {
+    let mut pipeline = FragmentPipeline::new(
+        reader,
+        *out_seqs,
+        options,
+        trimmer,
+        filter,
+        rejected_file,
+        n_threads,
+    )?;
+
+    let mut fragment = reader.new_fragment();
+    let mut num_fragments: usize = 0;
+    let sampling_stats = match sampling {
+        None => {
+            while !limits.reached(num_fragments) && reader.read(&mut fragment)? {
+                num_fragments += 1;
+                pipeline.process(fragment.clone(), true)?;
+            }
+            None
+        }
+        Some(sampling) => {
+            let mut sampling_stats = SamplingStats::new(sampling);
+            let full_stats = sampling.stats_on_full_input;
+            match sampling.method {
+                SamplingMethod::Fraction(fraction) => {
+                    let mut sampler = FractionSampler::new(fraction, sampling.seed);
+                    while !limits.reached(num_fragments) && reader.read(&mut fragment)? {
+                        num_fragments += 1;
+                        if sampler.keep() {
+                            sampling_stats.fragments_sampled += 1;
+                            pipeline.process(fragment.clone(), true)?;
+                        } else if full_stats {
+                            pipeline.add_to_input_stats(fragment.clone())?;
+                        }
+                    }
+                }
+                SamplingMethod::Count(count) => {
+                    let mut sampler = ReservoirSampler::new(count, sampling.seed);
+                    while !limits.reached(num_fragments) && reader.read(&mut fragment)? {
+                        num_fragments += 1;
+                        sampler.add(num_fragments, &fragment);
+                        if full_stats {
+                            pipeline.add_to_input_stats(fragment.clone())?;
+                        }
+                    }
+                    for (_, fragment) in sampler.into_sorted() {
+                        sampling_stats.fragments_sampled += 1;
+                        pipeline.process(fragment, !full_stats)?;
+                    }
+                }
+            }
+            sampling_stats.fragments_seen = num_fragments;
+            Some(sampling_stats)
+        }
+    };
+
+    let mut report = pipeline.finish()?;
+    report.sampling = sampling_stats;
+    report.partial = limits.partial_stats(num_fragments);
+    report.validation = reader.validation_report();
+    Ok(report)
 }
 
 #[derive(Parser)]
 #[command(
     name = "seq_stats",
     version = "0.1",
-    about = "Calculate GC content and length of sequences in a FASTQ file"
+    about = "Calculate GC content and length of sequences in a FASTA or FASTQ file",
+    after_help = "Exit status: 0 success, 1 other error, 2 invalid arguments, 3 failed QC check, \
+4 I/O error, 5 decompression error, 6 parse error, 7 output error",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
-    /// Input file path (default: "-" (stdin))
-    #[arg(default_value = "-")]
-    input_seq: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input file paths or glob patterns, R1 files in paired-end mode (default: "-" (stdin)).
+    /// Several inputs are read one after the other, their stats are reported in total and per input
+    input_seqs: Vec<String>,
 
-    /// Path to output JSON stats file
+    /// R2 file path or glob pattern, enables paired-end mode.
+    /// Repeat it to give an R2 file for each R1 input, the patterns are paired in sorted order
+    #[arg(long, conflicts_with = "interleaved")]
+    input_r2: Vec<String>,
+
+    /// File with an input path per line, or the R1 and R2 paths separated by a tab,
+    /// used instead of the input arguments
+    #[arg(long, conflicts_with_all = ["input_seqs", "input_r2"])]
+    input_list: Option<String>,
+
+    /// Input holds interleaved read pairs, enables paired-end mode
+    #[arg(long)]
+    interleaved: bool,
+
+    /// Path to output stats file
     #[arg(short, long, required = true)]
-    out_stats: String,
+    out_stats: Option<String>,
+
+    /// Overwrite existing output files, by default the run fails if any exists
+    #[arg(long)]
+    force: bool,
+
+    /// Format of the stats output
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// Also write a single file HTML report with plots of the stats
+    #[arg(long)]
+    html_report: Option<String>,
+
+    /// Sample name used in the MultiQC output and the HTML report (default: input file name without extensions)
+    #[arg(long)]
+    sample_name: Option<String>,
 
-    /// Output sequences to stdout
+    /// Output sequences to stdout, interleaved in paired-end mode
     #[arg(long)]
     seqs_to_stdout: bool,
+
+    /// Phred quality offset: 33 (Sanger/Illumina 1.8+) or 64 (Illumina 1.3-1.7)
+    #[arg(long, default_value_t = 33, value_parser = parse_phred_offset)]
+    phred_offset: u8,
+
+    /// Calculate the GC percentage over unambiguous bases (A, C, G, T) only
+    #[arg(long)]
+    gc_unambiguous_only: bool,
+
+    /// Maximum number of distinct sequences sampled to estimate the duplication levels
+    #[arg(long, default_value_t = 100_000)]
+    dup_max_tracked: usize,
+
+    /// Minimum fraction of the reads for a sequence to be reported as overrepresented
+    #[arg(long, default_value_t = 0.001, value_parser = parse_fraction)]
+    overrep_min_fraction: f64,
+
+    /// Number of bases at the start of the reads used to find overrepresented sequences, 0 for the whole read
+    #[arg(long, default_value_t = 50)]
+    overrep_prefix_len: usize,
+
+    /// Expected GC distribution, e.g. of a reference genome, to compare with the observed one:
+    /// a GC percentage and a read count or fraction per line
+    #[arg(long)]
+    expected_gc: Option<String>,
+
+    /// FASTA file with adapters to search in addition to the built-in ones, taken as 3' adapters
+    #[arg(long)]
+    adapters: Option<String>,
+
+    /// Do not search the built-in adapters (Illumina, Nextera, small RNA, SOLiD, Nanopore)
+    #[arg(long)]
+    no_builtin_adapters: bool,
+
+    /// Trim the 3' adapters searched for the adapter content from the 3' end of the reads:
+    /// the built-in Illumina, Nextera and SOLiD ones and the ones given with --adapters
+    #[arg(long)]
+    trim_adapters: bool,
+
+    /// Minimum overlap between the read end and an adapter start to trim a partial adapter
+    #[arg(long, default_value_t = 3, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    min_adapter_overlap: usize,
+
+    /// Trim the 3' end of the reads from the first window with a mean Phred score below this
+    #[arg(long)]
+    trim_qual: Option<u8>,
+
+    /// Size of the sliding window used by --trim-qual
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    trim_qual_window: u16,
+
+    /// Trim 3' poly-G tails at least this long
+    #[arg(long)]
+    trim_poly_g: Option<usize>,
+
+    /// Trim 3' poly-A tails at least this long
+    #[arg(long)]
+    trim_poly_a: Option<usize>,
+
+    /// Drop fragments with a read shorter than this after trimming (default: 1)
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    trim_min_len: Option<usize>,
+
+    /// Remove fragments with a read shorter than this
+    #[arg(long)]
+    filter_min_len: Option<usize>,
+
+    /// Remove fragments with a read longer than this
+    #[arg(long)]
+    filter_max_len: Option<usize>,
+
+    /// Remove fragments with a read with a larger fraction of N bases
+    #[arg(long, value_parser = parse_fraction)]
+    filter_max_n_fraction: Option<f64>,
+
+    /// Remove fragments with a read with a lower mean Phred score, FASTA reads always pass
+    #[arg(long)]
+    filter_min_mean_qual: Option<f64>,
+
+    /// Remove fragments with a read with a lower GC percentage
+    #[arg(long)]
+    filter_min_gc: Option<f64>,
+
+    /// Remove fragments with a read with a higher GC percentage
+    #[arg(long)]
+    filter_max_gc: Option<f64>,
+
+    /// Remove fragments with a read whose ID does not match this regular expression
+    #[arg(long)]
+    filter_id_regex: Option<String>,
+
+    /// Write the fragments removed by the filters to this file
+    #[arg(long)]
+    rejected_out: Option<String>,
+
+    /// Sample each fragment with this probability
+    #[arg(long, value_parser = parse_fraction, conflicts_with = "sample_count")]
+    sample_fraction: Option<f64>,
+
+    /// Sample this many fragments uniformly, keeping them in memory
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    sample_count: Option<u64>,
+
+    /// Seed of the random number generator used for sampling
+    #[arg(long, default_value_t = 0)]
+    sample_seed: u64,
+
+    /// Calculate the input stats on every fragment instead of only on the sampled ones
+    #[arg(long)]
+    stats_on_full_input: bool,
+
+    /// Stop after this many reads, or read pairs in paired-end mode, and mark the stats as partial
+    #[arg(long)]
+    max_reads: Option<usize>,
+
+    /// Stop after reading this many bytes of the input files, before decompression,
+    /// and mark the stats as partial
+    #[arg(long)]
+    max_bytes: Option<u64>,
+
+    /// JSON file with pass/warn/fail limits for total_reads, mean_quality, gc_deviation,
+    /// adapter_content and duplication, e.g. {"total_reads": {"fail_below": 100000}}.
+    /// The process exits with status 3 if a check fails
+    #[arg(long)]
+    qc_thresholds: Option<String>,
+
+    /// Check every FASTQ record strictly: the '@' header, the '+' separator, equal sequence
+    /// and quality lengths, the sequence and quality characters, duplicate read IDs and
+    /// Windows line endings. Stops at the first issue unless --validate-all is given
+    #[arg(long)]
+    validate: bool,
+
+    /// Report every validation issue instead of stopping at the first one,
+    /// leaving the fragments with invalid records out of the stats
+    #[arg(long, requires = "validate")]
+    validate_all: bool,
+
+    /// JSON file with the validation issues. If the validation fails the stats
+    /// and the HTML report are not written, but this file is
+    #[arg(long, requires = "validate")]
+    validation_report: Option<String>,
+
+    /// How to look for duplicate read IDs when validating
+    #[arg(long, value_enum, default_value_t = DupIdCheck::Exact)]
+    validate_dup_ids: DupIdCheck,
+
+    /// Size in MB of the Bloom filter used by --validate-dup-ids bloom
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..))]
+    validate_bloom_mb: u64,
+
+    /// Number of threads used to decompress gzip input and to compute the stats
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: u16,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Merge stats JSON files written by seq_stats, e.g. of chunks of the same sample,
+    /// summing their histograms. The duplication levels can not be merged and are left out,
+    /// and the overrepresented sequences are counted from the ones listed in the files
+    Merge(MergeArgs),
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// Stats JSON file paths or glob patterns
+    #[arg(required = true)]
+    stats_files: Vec<String>,
+
+    /// Path to output stats file
+    #[arg(short, long)]
+    out_stats: String,
+
+    /// Overwrite existing output files, by default the merge fails if any exists
+    #[arg(long)]
+    force: bool,
+
+    /// Format of the stats output
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// Sample name used in the MultiQC output
+    #[arg(long, default_value = "merged")]
+    sample_name: String,
+
+    /// Minimum fraction of the merged reads for a sequence to be reported as overrepresented,
+    /// a sequence is only counted in the files that list it
+    #[arg(long, default_value_t = 0.001, value_parser = parse_fraction)]
+    overrep_min_fraction: f64,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn parse_phred_offset(value: &str) -> Result<u8, String> {
+    match value {
+        "33" => Ok(33),
+        "64" => Ok(64),
+        _ => Err(format!("invalid Phred offset {}, use 33 or 64", value)),
+    }
+}
+
+/// Parses a fraction in the (0, 1] range.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!(
+            "invalid fraction {}, use a number in (0, 1]",
+            value
+        )),
+    }
+}
+
+fn main() {
     let args = Cli::parse();
-    let in_seq_fpath = &args.input_seq;
-    let out_stats_fpath = &args.out_stats;
+    let result = match &args.command {
+        Some(Command::Merge(merge_args)) => run_merge(merge_args).map(|_| None),
+        None => run(&args),
+    };
+    match result {
+        Ok(Some(Status::Fail)) => std::process::exit(qc::QC_FAIL_EXIT_CODE),
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(error::exit_code(err.as_ref()));
+        }
+    }
+}
+
+/// Calculates and writes the stats, returns the QC status if there are QC thresholds.
+fn run(args: &Cli) -> Result<Option<Status>, Box<dyn std::error::Error>> {
+    let inputs = match &args.input_list {
+        Some(list_fpath) => inputs::read_input_list(list_fpath)?,
+        None if args.input_seqs.is_empty() => {
+            inputs::expand_inputs(&["-".to_string()], &args.input_r2)?
+        }
+        None => inputs::expand_inputs(&args.input_seqs, &args.input_r2)?,
+    };
+    let paired_files = inputs[0].r2_path.is_some();
+    if paired_files && args.interleaved {
+        return Err("--interleaved can not be used with R2 files".into());
+    }
+    let Some(out_stats_fpath) = &args.out_stats else {
+        unreachable!("--out-stats is required without a subcommand");
+    };
     let out_seqs = &args.seqs_to_stdout;
+    let n_threads = args.threads as usize;
+
+    let mut adapters = if args.no_builtin_adapters {
+        Vec::new()
+    } else {
+        adapters::builtin_adapters()
+    };
+    if let Some(adapters_fpath) = &args.adapters {
+        adapters.extend(adapters::read_adapters(adapters_fpath)?);
+    }
+
+    let trimming = args.trim_adapters
+        || args.trim_qual.is_some()
+        || args.trim_poly_g.is_some()
+        || args.trim_poly_a.is_some()
+        || args.trim_min_len.is_some();
+    let trimmer = trimming.then(|| {
+        Trimmer::new(TrimOptions {
+            adapters: if args.trim_adapters {
+                adapters
+                    .iter()
+                    .filter(|adapter| adapter.three_prime)
+                    .cloned()
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            min_adapter_overlap: args.min_adapter_overlap,
+            quality_window: args
+                .trim_qual
+                .map(|min_qual| (args.trim_qual_window as usize, min_qual)),
+            poly_g_min_len: args.trim_poly_g,
+            poly_a_min_len: args.trim_poly_a,
+            min_len: args.trim_min_len.unwrap_or(1),
+            phred_offset: args.phred_offset,
+        })
+    });
+
+    let filtering = args.filter_min_len.is_some()
+        || args.filter_max_len.is_some()
+        || args.filter_max_n_fraction.is_some()
+        || args.filter_min_mean_qual.is_some()
+        || args.filter_min_gc.is_some()
+        || args.filter_max_gc.is_some()
+        || args.filter_id_regex.is_some();
+    let filter = if filtering {
+        Some(ReadFilter::new(FilterOptions {
+            min_len: args.filter_min_len,
+            max_len: args.filter_max_len,
+            max_n_fraction: args.filter_max_n_fraction,
+            min_mean_qual: args.filter_min_mean_qual,
+            min_gc: args.filter_min_gc,
+            max_gc: args.filter_max_gc,
+            id_regex: args
+                .filter_id_regex
+                .as_deref()
+                .map(regex::Regex::new)
+                .transpose()?,
+            phred_offset: args.phred_offset,
+            gc_unambiguous_only: args.gc_unambiguous_only,
+        }))
+    } else {
+        None
+    };
+
+    let sampling_method = match (args.sample_fraction, args.sample_count) {
+        (Some(fraction), _) => Some(SamplingMethod::Fraction(fraction)),
+        (None, Some(count)) => Some(SamplingMethod::Count(count as usize)),
+        (None, None) => None,
+    };
+    let sampling = sampling_method.map(|method| SamplingOptions {
+        method,
+        seed: args.sample_seed,
+        stats_on_full_input: args.stats_on_full_input,
+    });
+
+    let options = StatsOptions {
+        phred_offset: args.phred_offset,
+        gc_unambiguous_only: args.gc_unambiguous_only,
+        dup_max_tracked: args.dup_max_tracked,
+        overrep_min_fraction: args.overrep_min_fraction,
+        overrep_prefix_len: args.overrep_prefix_len,
+        adapters,
+        expected_gc: args
+            .expected_gc
+            .as_deref()
+            .map(gc_model::read_expected_gc)
+            .transpose()?,
+    };
+
+    let sample_name = match &args.sample_name {
+        Some(sample_name) => sample_name.clone(),
+        None => output::sample_name_from_path(&inputs[0].path, paired_files),
+    };
+
+    let qc_thresholds = args
+        .qc_thresholds
+        .as_deref()
+        .map(QcThresholds::from_file)
+        .transpose()?;
+
+    let validation = args.validate.then(|| ValidateOptions {
+        collect_all: args.validate_all,
+        dup_id_check: args.validate_dup_ids,
+        bloom_bytes: args.validate_bloom_mb as usize * 1024 * 1024,
+        phred_offset: args.phred_offset,
+        interleaved: args.interleaved,
+    });
+
+    // Create the output files before reading the input, to fail early if they can not be
+    // created or would be overwritten. They are only renamed into place once written
+    let overwrite = args.force;
+    let create_file =
+        |fpath: &str| AtomicFile::create(fpath, overwrite).map_err(|err| output_error(fpath, err));
+    let stats_output = StatsOutput::create(out_stats_fpath, args.format, overwrite)
+        .map_err(|err| output_error(out_stats_fpath, err))?;
+    let html_file = args.html_report.as_deref().map(create_file).transpose()?;
+    let validation_file = args
+        .validation_report
+        .as_deref()
+        .map(create_file)
+        .transpose()?;
+    let rejected_file = args.rejected_out.as_deref().map(create_file).transpose()?;
+
+    let (mut reader, progress) =
+        MultiFragmentReader::open(inputs, args.interleaved, validation.as_ref(), n_threads)?;
+    let result = calc_read_stats(
+        &mut reader,
+        ReadLimits::new(args.max_reads, args.max_bytes, progress),
+        out_seqs,
+        &options,
+        trimmer.as_ref(),
+        filter.as_ref(),
+        rejected_file,
+        sampling.as_ref(),
+        n_threads,
+    );
+    // The issues are also written when the reading stopped at one of them
+    if let (Some(file), Some(validation)) = (validation_file, reader.validation_report()) {
+        let fpath = file.path().to_string();
+        atomic_file::write_atomically(file, |writer| {
+            Ok(serde_json::to_writer_pretty(writer, &validation)?)
+        })
+        .map_err(|err| output_error(&fpath, err))?;
+    }
+    let mut report = result?;
+    report.qc_status = qc_thresholds
+        .as_ref()
+        .map(|thresholds| thresholds.check(&report.stats));
+
+    // The stats are not written for invalid input, their presence means that it passed
+    if let Some(validation) = report.validation.as_ref().filter(|report| !report.valid) {
+        let listed_in = match (&args.validation_report, validation.issues.first()) {
+            (Some(fpath), _) => format!("listed in {}", fpath),
+            (None, Some(issue)) => format!(
+                "the first in {} at line {}: {}",
+                issue.file, issue.line, issue.message
+            ),
+            (None, None) => "none listed".to_string(),
+        };
+        return Err(SeqStatsError::new(
+            ErrorKind::Parse,
+            format!(
+                "validation found {} issues, {}",
+                validation.num_issues, listed_in
+            ),
+        )
+        .into());
+    }
 
-    let _ = calc_read_stats(in_seq_fpath, out_stats_fpath, out_seqs);
+    // write stats to file
+    stats_output
+        .write(&sample_name, &report)
+        .map_err(|err| output_error(out_stats_fpath, err))?;
+    if let Some(html_file) = html_file {
+        let html_fpath = html_file.path().to_string();
+        report::write_html_report(html_file, &sample_name, &report)
+            .map_err(|err| output_error(&html_fpath, err))?;
+    }
+    Ok(report.qc_status.map(|qc_status| qc_status.status))
+}
 
+/// Merges stats files and writes the merged stats.
+fn run_merge(args: &MergeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let stats_output = StatsOutput::create(&args.out_stats, args.format, args.force)
+        .map_err(|err| output_error(&args.out_stats, err))?;
+    let stats_fpaths = inputs::expand_globs(&args.stats_files)?;
+    let merged = merge::merge_stats_files(&stats_fpaths, args.overrep_min_fraction)?;
+    stats_output
+        .write(&args.sample_name, &merged)
+        .map_err(|err| output_error(&args.out_stats, err))?;
     Ok(())
 }
 
-/*
-TODO
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Runs the stats of a FASTQ file with validation, returns the error and the validation report
+    fn run_invalid(name: &str, fastq: &str, extra_args: &[&str]) -> (String, serde_json::Value) {
+        let dir = std::env::temp_dir().join(format!("seq_stats_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |file_name: &str| dir.join(file_name).to_string_lossy().into_owned();
+        fs::write(path("in.fq"), fastq).unwrap();
+        let mut cli_args = vec![
+            "seq_stats".to_string(),
+            path("in.fq"),
+            "-o".to_string(),
+            path("stats.json"),
+            "--validate".to_string(),
+            "--validation-report".to_string(),
+            path("validation.json"),
+        ];
+        cli_args.extend(extra_args.iter().map(|arg| arg.to_string()));
+        let args = Cli::try_parse_from(cli_args).unwrap();
+
+        let err = run(&args).unwrap_err();
+        assert_eq!(error::exit_code(err.as_ref()), 6);
+        assert!(!Path::new(&path("stats.json")).exists());
+        let report = serde_json::from_str(&fs::read_to_string(path("validation.json")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        (err.to_string(), report.unwrap())
+    }
+
+    #[test]
+    fn validation_report_is_written_when_stopping_at_the_first_issue() {
+        let (err, report) = run_invalid("stop", "@r1\nACXT\n+\nIIII\n@r2\nACGT\n+\nIIII\n", &[]);
+        assert!(err.contains("line 2"), "{}", err);
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["issues"][0]["kind"], "illegal_seq_char");
+    }
 
-Add tests
+    #[test]
+    fn validation_report_is_written_when_the_parser_fails() {
+        let fastq = "@r1\nACXT\n+\nIIII\nr2\nACGT\n+\nIIII\n";
+        let (_, report) = run_invalid("parser", fastq, &["--validate-all"]);
+        assert_eq!(report["num_issues"], 2);
+        assert_eq!(report["issues"][1]["kind"], "missing_header_at");
+    }
 
- */
+    #[test]
+    fn validation_report_is_written_when_collecting_the_issues() {
+        let fastq = "@r1\nACXT\n+\nIIII\n@r2\nACGT\n+\nIIII\n";
+        let (err, report) = run_invalid("collect", fastq, &["--validate-all"]);
+        assert!(err.contains("listed in"), "{}", err);
+        assert_eq!(report["num_issues"], 1);
+        assert_eq!(report["invalid_records"], 1);
+    }
+}
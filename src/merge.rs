@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::error::{ErrorKind, SeqStatsError};
+use crate::stats::{FragmentStats, PairedReadStats, ReadStats};
+
+/// Stats summed over several stats files
+#[derive(Serialize)]
+pub struct MergedStats {
+    #[serde(flatten)]
+    pub stats: FragmentStats,
+    /// Only merged if every file has it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_trimming: Option<FragmentStats>,
+    /// Stats files merged, in order
+    pub merged_files: Vec<String>,
+}
+
+/// Reads the JSON stats files written by seq_stats and sums their stats.
+/// The histograms and per position counts are summed and the summaries derived from them
+/// computed again, as if the reads of every file had been read in a single run.
+/// The duplication levels can not be merged and are left out, and the overrepresented
+/// sequences are the ones listed in any file, with their counts summed, that reach
+/// `overrep_min_fraction` of the merged reads.
+/// The other sections, like the trimming, filtering and QC ones, are not merged.
+pub fn merge_stats_files(
+    paths: &[String],
+    overrep_min_fraction: f64,
+) -> Result<MergedStats, SeqStatsError> {
+    let mut stats = None;
+    let mut after_trimming = None;
+    for (file_idx, path) in paths.iter().enumerate() {
+        let value = read_json(path)?;
+        let merge_error = |msg: String| SeqStatsError::new(ErrorKind::Parse, msg).in_file(path);
+
+        let file_stats =
+            fragment_stats_from_value(&value, overrep_min_fraction).map_err(merge_error)?;
+        stats = Some(match stats {
+            None => file_stats,
+            Some(stats) => merge_fragment_stats(stats, file_stats).map_err(merge_error)?,
+        });
+
+        let file_after_trimming = value
+            .get("after_trimming")
+            .map(|value| fragment_stats_from_value(value, overrep_min_fraction))
+            .transpose()
+            .map_err(merge_error)?;
+        after_trimming = match (after_trimming, file_after_trimming) {
+            (None, Some(file_after_trimming)) if file_idx == 0 => Some(file_after_trimming),
+            (Some(after_trimming), Some(file_after_trimming)) => Some(
+                merge_fragment_stats(after_trimming, file_after_trimming).map_err(merge_error)?,
+            ),
+            _ => None,
+        };
+    }
+    let Some(mut stats) = stats else {
+        return Err(SeqStatsError::new(
+            ErrorKind::Parse,
+            "no stats files to merge",
+        ));
+    };
+    finish_merged(&mut stats);
+    if let Some(after_trimming) = &mut after_trimming {
+        finish_merged(after_trimming);
+    }
+    Ok(MergedStats {
+        stats,
+        after_trimming,
+        merged_files: paths.to_vec(),
+    })
+}
+
+fn read_json(path: &str) -> Result<Value, SeqStatsError> {
+    let file = File::open(path)
+        .map_err(|err| SeqStatsError::new(ErrorKind::Io, err.to_string()).in_file(path))?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+        SeqStatsError::new(ErrorKind::Parse, format!("not a JSON stats file: {}", err))
+            .in_file(path)
+    })
+}
+
+/// Reads the single or paired-end stats of a stats file section, ready to be merged.
+fn fragment_stats_from_value(
+    value: &Value,
+    overrep_min_fraction: f64,
+) -> Result<FragmentStats, String> {
+    let invalid_stats = |err: serde_json::Error| format!("invalid stats: {}", err);
+    if value.get("r1").is_some() {
+        let mut stats = PairedReadStats::deserialize(value).map_err(invalid_stats)?;
+        for mate_stats in [&mut stats.r1, &mut stats.r2, &mut stats.combined] {
+            mate_stats.restore_accumulators(overrep_min_fraction)?;
+        }
+        Ok(FragmentStats::Paired(Box::new(stats)))
+    } else {
+        let mut stats = ReadStats::deserialize(value).map_err(invalid_stats)?;
+        stats.restore_accumulators(overrep_min_fraction)?;
+        Ok(FragmentStats::Single(Box::new(stats)))
+    }
+}
+
+fn merge_fragment_stats(
+    stats: FragmentStats,
+    other: FragmentStats,
+) -> Result<FragmentStats, String> {
+    match (stats, other) {
+        (FragmentStats::Single(mut stats), FragmentStats::Single(other)) => {
+            merge_read_stats(&mut stats, &other)?;
+            Ok(FragmentStats::Single(stats))
+        }
+        (FragmentStats::Paired(mut stats), FragmentStats::Paired(other)) => {
+            stats.total_pairs += other.total_pairs;
+            merge_read_stats(&mut stats.r1, &other.r1)?;
+            merge_read_stats(&mut stats.r2, &other.r2)?;
+            merge_read_stats(&mut stats.combined, &other.combined)?;
+            Ok(FragmentStats::Paired(stats))
+        }
+        _ => Err("single and paired-end stats can not be merged".to_string()),
+    }
+}
+
+fn merge_read_stats(stats: &mut ReadStats, other: &ReadStats) -> Result<(), String> {
+    // The adapter hits are merged in order
+    let adapter_names = |stats: &ReadStats| -> Vec<String> {
+        stats
+            .adapter_content
+            .iter()
+            .map(|content| content.name.clone())
+            .collect()
+    };
+    if adapter_names(stats) != adapter_names(other) {
+        return Err("the adapters differ from the ones of the previous files".to_string());
+    }
+    stats.merge(other);
+    Ok(())
+}
+
+fn finish_merged(stats: &mut FragmentStats) {
+    let mate_stats = match stats {
+        FragmentStats::Single(stats) => vec![stats.as_mut()],
+        FragmentStats::Paired(stats) => vec![&mut stats.r1, &mut stats.r2, &mut stats.combined],
+    };
+    for stats in mate_stats {
+        stats.finish();
+        stats.duplication = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overrepresented::OverrepTracker;
+    use crate::stats::test_fixtures::{options, random_reads};
+    use std::fs;
+
+    const OVERREP_MIN_FRACTION: f64 = 0.01;
+
+    /// Random reads, every tenth one the same read with an adapter, to be overrepresented
+    fn reads(num_reads: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut reads = random_reads(num_reads);
+        let seq = b"ACGTTGCAGGCTAGCTAGGATCAGATCGGAAGAGCACACGTCTGAACTCCAGTCAC";
+        for read in reads.iter_mut().step_by(10) {
+            *read = (seq.to_vec(), vec![b'I'; seq.len()]);
+        }
+        reads
+    }
+
+    fn read_stats(reads: &[(Vec<u8>, Vec<u8>)]) -> ReadStats {
+        let options = options();
+        let mut stats = ReadStats::new();
+        // The overrepresented sequences are tracked by the caller, in read order
+        stats.overrep_tracker =
+            OverrepTracker::new(OVERREP_MIN_FRACTION, options.overrep_prefix_len);
+        for (seq, qual) in reads {
+            stats.add_read(seq, Some(qual), &options).unwrap();
+            stats.overrep_tracker.add(seq);
+        }
+        stats.finish();
+        stats
+    }
+
+    #[test]
+    fn merged_chunks_equal_a_single_run() {
+        let reads = reads(5000);
+        let dir = std::env::temp_dir().join(format!("seq_stats_merge_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<String> = reads
+            .chunks(1700)
+            .enumerate()
+            .map(|(idx, chunk)| {
+                let path = dir.join(format!("chunk_{}.json", idx));
+                let json = serde_json::to_string(&read_stats(chunk)).unwrap();
+                fs::write(&path, json).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect();
+
+        let merged = merge_stats_files(&paths, OVERREP_MIN_FRACTION).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(merged.after_trimming.is_none());
+        let mut single_run = read_stats(&reads);
+        single_run.duplication = None;
+        let merged_value = serde_json::to_value(&merged.stats).unwrap();
+        assert_eq!(merged_value["total_records"], 5000);
+        assert_eq!(merged_value["overrepresented_sequences"][0]["count"], 500);
+        assert_eq!(merged_value, serde_json::to_value(&single_run).unwrap());
+    }
+
+    #[test]
+    fn single_and_paired_stats_are_not_merged() {
+        let reads = reads(100);
+        let single = FragmentStats::Single(Box::new(read_stats(&reads)));
+        let paired = FragmentStats::Paired(Box::new(PairedReadStats::new(
+            read_stats(&reads),
+            read_stats(&reads),
+        )));
+        let to_mergeable = |stats: &FragmentStats| {
+            let value = serde_json::to_value(stats).unwrap();
+            fragment_stats_from_value(&value, OVERREP_MIN_FRACTION).unwrap()
+        };
+        let paired_sum =
+            merge_fragment_stats(to_mergeable(&paired), to_mergeable(&paired)).unwrap();
+        let FragmentStats::Paired(paired_sum) = paired_sum else {
+            panic!("paired stats merged into single ones");
+        };
+        assert_eq!(paired_sum.total_pairs, 200);
+        assert!(merge_fragment_stats(to_mergeable(&single), to_mergeable(&paired)).is_err());
+    }
+}
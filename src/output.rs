@@ -0,0 +1,706 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::atomic_file::{check_clobber, write_atomically, write_uncommitted, AtomicFile};
+
+/// Format of the stats output
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Indented JSON
+    Json,
+    /// JSON in a single line
+    JsonCompact,
+    /// One TSV file per table, named `<out>.<table>.tsv` after the output path without its .tsv extension
+    Tsv,
+    Yaml,
+    /// MultiQC custom content, one `<out>.<section>_mqc.json` file per section
+    Multiqc,
+}
+
+/// Output of the stats in the given format.
+/// Every file is written atomically, the files of the TSV and MultiQC formats are renamed
+/// into place only once all of them are written, the main one last.
+/// Existing files are only replaced with `overwrite`.
+pub struct StatsOutput {
+    out_stats_fpath: String,
+    format: OutputFormat,
+    overwrite: bool,
+    /// File that is always written, created under its temporary name
+    main_file: AtomicFile,
+}
+
+impl StatsOutput {
+    /// Creates the main stats file under its temporary name and checks that no other file of
+    /// the TSV and MultiQC formats would be overwritten, so that a missing directory or
+    /// an existing output is found before the input is read.
+    pub fn create(
+        out_stats_fpath: &str,
+        format: OutputFormat,
+        overwrite: bool,
+    ) -> io::Result<Self> {
+        let main_file = AtomicFile::create(&main_stats_path(out_stats_fpath, format), overwrite)?;
+        if !overwrite {
+            match format {
+                OutputFormat::Tsv => check_sibling_clobber(tsv_stem(out_stats_fpath), ".tsv")?,
+                OutputFormat::Multiqc => {
+                    check_sibling_clobber(multiqc_stem(out_stats_fpath), "_mqc.json")?
+                }
+                _ => {}
+            }
+        }
+        Ok(StatsOutput {
+            out_stats_fpath: out_stats_fpath.to_string(),
+            format,
+            overwrite,
+            main_file,
+        })
+    }
+
+    /// Writes the stats. `sample_name` names the sample in the MultiQC sections.
+    pub fn write<T: Serialize>(
+        self,
+        sample_name: &str,
+        stats: &T,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let out_stats_fpath = self.out_stats_fpath.as_str();
+        let main_fpath = self.main_file.path().to_string();
+        match self.format {
+            OutputFormat::Json => write_atomically(self.main_file, |writer| {
+                Ok(serde_json::to_writer_pretty(writer, stats)?)
+            })?,
+            OutputFormat::JsonCompact => write_atomically(self.main_file, |writer| {
+                Ok(serde_json::to_writer(writer, stats)?)
+            })?,
+            OutputFormat::Yaml => {
+                let yaml = to_yaml(&serde_json::to_value(stats)?);
+                write_atomically(self.main_file, |writer| {
+                    Ok(writer.write_all(yaml.as_bytes())?)
+                })?
+            }
+            OutputFormat::Tsv => {
+                let tables = tsv_tables(&serde_json::to_value(stats)?);
+                let mut main_file = Some(self.main_file);
+                let files = tables
+                    .into_iter()
+                    .map(|table| {
+                        let fpath = format!("{}.{}.tsv", tsv_stem(out_stats_fpath), table.name);
+                        let file = output_file(&mut main_file, &fpath, self.overwrite)?;
+                        write_uncommitted(file, |writer| {
+                            Ok(writer.write_all(table.to_tsv().as_bytes())?)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                commit_all(files, &main_fpath)?;
+            }
+            OutputFormat::Multiqc => {
+                let value = serde_json::to_value(stats)?;
+                let mut main_file = Some(self.main_file);
+                let files = multiqc_sections(&value, sample_name)
+                    .into_iter()
+                    .map(|(section_name, section)| {
+                        let fpath = format!(
+                            "{}.{}_mqc.json",
+                            multiqc_stem(out_stats_fpath),
+                            section_name
+                        );
+                        let file = output_file(&mut main_file, &fpath, self.overwrite)?;
+                        write_uncommitted(file, |writer| {
+                            Ok(serde_json::to_writer_pretty(writer, &section)?)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                commit_all(files, &main_fpath)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The main file if `fpath` is its path, a new file otherwise.
+fn output_file(
+    main_file: &mut Option<AtomicFile>,
+    fpath: &str,
+    overwrite: bool,
+) -> io::Result<AtomicFile> {
+    match main_file.take_if(|main_file| main_file.path() == fpath) {
+        Some(main_file) => Ok(main_file),
+        None => AtomicFile::create(fpath, overwrite),
+    }
+}
+
+/// Fails if a file named `<stem>.<name><suffix>` exists.
+fn check_sibling_clobber(stem: &str, suffix: &str) -> io::Result<()> {
+    let stem = Path::new(stem);
+    let dir = match stem.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        stem.file_name().unwrap_or_default().to_string_lossy()
+    );
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with(&prefix) && file_name.ends_with(suffix) {
+            let fpath = stem.with_file_name(file_name.as_ref());
+            return check_clobber(&fpath.to_string_lossy(), false);
+        }
+    }
+    Ok(())
+}
+
+/// Commits the files, the main one last, as its presence tells that the output is complete.
+fn commit_all(mut files: Vec<AtomicFile>, main_fpath: &str) -> io::Result<()> {
+    files.sort_by_key(|file| file.path() == main_fpath);
+    files.into_iter().try_for_each(AtomicFile::commit)
+}
+
+fn tsv_stem(out_stats_fpath: &str) -> &str {
+    out_stats_fpath
+        .strip_suffix(".tsv")
+        .unwrap_or(out_stats_fpath)
+}
+
+fn multiqc_stem(out_stats_fpath: &str) -> &str {
+    out_stats_fpath
+        .strip_suffix("_mqc.json")
+        .or_else(|| out_stats_fpath.strip_suffix(".json"))
+        .unwrap_or(out_stats_fpath)
+}
+
+/// File that is always written
+fn main_stats_path(out_stats_fpath: &str, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json | OutputFormat::JsonCompact | OutputFormat::Yaml => {
+            out_stats_fpath.to_string()
+        }
+        OutputFormat::Tsv => format!("{}.summary.tsv", tsv_stem(out_stats_fpath)),
+        OutputFormat::Multiqc => format!("{}.general_mqc.json", multiqc_stem(out_stats_fpath)),
+    }
+}
+
+/// Sample name taken from the input file name, without the sequence format and compression extensions.
+/// For an R1 file of a pair the `_R1` or `_1` suffix is also removed.
+pub fn sample_name_from_path(path: &str, r1_file: bool) -> String {
+    if path == "-" {
+        return "stdin".to_string();
+    }
+    let mut name = path.rsplit('/').next().unwrap_or(path);
+    for extensions in [
+        &[".gz", ".bgz", ".zst", ".bz2", ".xz"][..],
+        &[".fastq", ".fq", ".fasta", ".fa", ".fna"][..],
+    ] {
+        if let Some(stripped) = extensions.iter().find_map(|ext| name.strip_suffix(ext)) {
+            name = stripped;
+        }
+    }
+    if r1_file {
+        name = ["_R1", "_1"]
+            .iter()
+            .find_map(|suffix| name.strip_suffix(suffix))
+            .unwrap_or(name);
+    }
+    name.to_string()
+}
+
+fn scalar_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(value, Value::Array(_) | Value::Object(_))
+}
+
+/// Histograms are serialized as maps from numbers to counts
+fn is_histogram(map: &Map<String, Value>) -> bool {
+    !map.is_empty()
+        && map
+            .iter()
+            .all(|(key, value)| key.parse::<f64>().is_ok() && value.is_number())
+}
+
+struct Table {
+    name: String,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn to_tsv(&self) -> String {
+        let mut tsv = self.header.join("\t");
+        tsv.push('\n');
+        for row in &self.rows {
+            tsv.push_str(&row.join("\t"));
+            tsv.push('\n');
+        }
+        tsv
+    }
+}
+
+/// Splits the stats in tables: a `summary` table with the single values,
+/// a table per histogram and per list of records, named by their path in the stats.
+fn tsv_tables(value: &Value) -> Vec<Table> {
+    let mut summary = Table {
+        name: "summary".to_string(),
+        header: vec!["metric".to_string(), "value".to_string()],
+        rows: Vec::new(),
+    };
+    let mut tables = Vec::new();
+    collect_tables("", value, &mut summary, &mut tables);
+    if !summary.rows.is_empty() {
+        tables.insert(0, summary);
+    }
+    tables
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn collect_tables(path: &str, value: &Value, summary: &mut Table, tables: &mut Vec<Table>) {
+    match value {
+        Value::Object(map) if is_histogram(map) => {
+            let key_name = path.rsplit('.').next().unwrap_or(path);
+            tables.push(Table {
+                name: path.to_string(),
+                header: vec![
+                    key_name.trim_end_matches("_distrib").to_string(),
+                    "count".to_string(),
+                ],
+                rows: map
+                    .iter()
+                    .map(|(key, count)| vec![key.clone(), scalar_to_string(count)])
+                    .collect(),
+            });
+        }
+        Value::Object(map) => {
+            for (key, field) in map {
+                collect_tables(&join_path(path, key), field, summary, tables);
+            }
+        }
+        Value::Array(items) if items.iter().all(Value::is_object) => {
+            collect_record_tables(path, items, tables)
+        }
+        Value::Array(items) => tables.push(Table {
+            name: path.to_string(),
+            header: vec!["index".to_string(), "value".to_string()],
+            rows: items
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| vec![idx.to_string(), scalar_to_string(item)])
+                .collect(),
+        }),
+        scalar => summary
+            .rows
+            .push(vec![path.to_string(), scalar_to_string(scalar)]),
+    }
+}
+
+/// A list of records becomes a table with a column per scalar field.
+/// Each list of values inside the records becomes a long table with the scalar fields,
+/// the index in the list and the value.
+fn collect_record_tables(path: &str, records: &[Value], tables: &mut Vec<Table>) {
+    let Some(Value::Object(first)) = records.first() else {
+        return;
+    };
+    let scalar_fields: Vec<&String> = first
+        .iter()
+        .filter(|(_, value)| is_scalar(value))
+        .map(|(key, _)| key)
+        .collect();
+    let list_fields: Vec<&String> = first
+        .iter()
+        .filter(|(_, value)| matches!(value, Value::Array(_)))
+        .map(|(key, _)| key)
+        .collect();
+    let scalar_row = |record: &Value| -> Vec<String> {
+        scalar_fields
+            .iter()
+            .map(|field| scalar_to_string(&record[field.as_str()]))
+            .collect()
+    };
+
+    tables.push(Table {
+        name: path.to_string(),
+        header: scalar_fields
+            .iter()
+            .map(|field| field.to_string())
+            .collect(),
+        rows: records.iter().map(scalar_row).collect(),
+    });
+    for list_field in list_fields {
+        let mut header: Vec<String> = scalar_fields
+            .iter()
+            .map(|field| field.to_string())
+            .collect();
+        header.push("index".to_string());
+        header.push(list_field.to_string());
+        let mut rows = Vec::new();
+        for record in records {
+            let Some(values) = record[list_field.as_str()].as_array() else {
+                continue;
+            };
+            for (idx, value) in values.iter().enumerate() {
+                let mut row = scalar_row(record);
+                row.push(idx.to_string());
+                row.push(scalar_to_string(value));
+                rows.push(row);
+            }
+        }
+        tables.push(Table {
+            name: join_path(path, list_field),
+            header,
+            rows,
+        });
+    }
+}
+
+fn is_plain_yaml_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Array(items) if items.is_empty() => "[]".to_string(),
+        Value::Object(map) if map.is_empty() => "{}".to_string(),
+        // JSON strings are valid double-quoted YAML scalars
+        other => other.to_string(),
+    }
+}
+
+fn is_yaml_block(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+        _ => false,
+    }
+}
+
+/// Block style YAML, every line indented by `indent` spaces
+fn yaml_block(value: &Value, indent: usize) -> String {
+    let padding = " ".repeat(indent);
+    let mut yaml = String::new();
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map {
+                let key = if is_plain_yaml_key(key) {
+                    key.clone()
+                } else {
+                    Value::String(key.clone()).to_string()
+                };
+                if is_yaml_block(field) {
+                    yaml.push_str(&format!("{}{}:\n", padding, key));
+                    yaml.push_str(&yaml_block(field, indent + 2));
+                } else {
+                    yaml.push_str(&format!("{}{}: {}\n", padding, key, yaml_scalar(field)));
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                if is_yaml_block(item) {
+                    // The first line of the nested block goes after the dash
+                    let block = yaml_block(item, indent + 2);
+                    yaml.push_str(&padding);
+                    yaml.push_str("- ");
+                    yaml.push_str(&block[indent + 2..]);
+                } else {
+                    yaml.push_str(&format!("{}- {}\n", padding, yaml_scalar(item)));
+                }
+            }
+        }
+        scalar => yaml.push_str(&format!("{}{}\n", padding, yaml_scalar(scalar))),
+    }
+    yaml
+}
+
+fn to_yaml(value: &Value) -> String {
+    format!("---\n{}", yaml_block(value, 0))
+}
+
+/// Stats of each mate, named after the sample
+pub fn mate_stats<'a>(value: &'a Value, sample_name: &str) -> Vec<(String, &'a Value)> {
+    match (value.get("r1"), value.get("r2")) {
+        (Some(r1), Some(r2)) => vec![
+            (format!("{}_R1", sample_name), r1),
+            (format!("{}_R2", sample_name), r2),
+        ],
+        _ => vec![(sample_name.to_string(), value)],
+    }
+}
+
+/// Mean of a histogram serialized as a map from numbers to counts
+pub fn histogram_mean(histogram: &Value) -> Option<f64> {
+    let histogram = histogram.as_object()?;
+    let mut total = 0.0;
+    let mut sum = 0.0;
+    for (key, count) in histogram {
+        let count = count.as_f64()?;
+        total += count;
+        sum += key.parse::<f64>().ok()? * count;
+    }
+    (total > 0.0).then(|| sum / total)
+}
+
+fn linegraph_section(
+    id: &str,
+    section_name: &str,
+    xlab: &str,
+    ylab: &str,
+    data: Map<String, Value>,
+) -> Value {
+    json!({
+        "id": format!("seq_stats_{}", id),
+        "section_name": section_name,
+        "plot_type": "linegraph",
+        "pconfig": {
+            "id": format!("seq_stats_{}_plot", id),
+            "title": format!("seq_stats: {}", section_name),
+            "xlab": xlab,
+            "ylab": ylab,
+        },
+        "data": data,
+    })
+}
+
+/// MultiQC custom content sections: general stats, GC content, read length and
+/// mean quality by position, with a sample per mate for read pairs.
+fn multiqc_sections(value: &Value, sample_name: &str) -> Vec<(&'static str, Value)> {
+    let mates = mate_stats(value, sample_name);
+
+    let mut general = Map::new();
+    let mut gc = Map::new();
+    let mut len = Map::new();
+    let mut qual = Map::new();
+    for (name, stats) in &mates {
+        general.insert(
+            name.clone(),
+            json!({
+                "total_records": stats["total_records"],
+                "mean_gc": histogram_mean(&stats["gc_distrib"]),
+                "mean_len": histogram_mean(&stats["len_distrib"]),
+            }),
+        );
+        gc.insert(name.clone(), stats["gc_distrib"].clone());
+        len.insert(name.clone(), stats["len_distrib"].clone());
+        if let Some(positions) = stats["qual_by_position"].as_array() {
+            let means: Map<String, Value> = positions
+                .iter()
+                .map(|position| (position["position"].to_string(), position["mean"].clone()))
+                .collect();
+            qual.insert(name.clone(), Value::Object(means));
+        }
+    }
+
+    let mut sections = vec![
+        (
+            "general",
+            json!({
+                "id": "seq_stats_general",
+                "plot_type": "generalstats",
+                "pconfig": [
+                    {"total_records": {"title": "Reads", "format": "{:,.0f}"}},
+                    {"mean_gc": {"title": "% GC", "max": 100, "min": 0, "suffix": "%"}},
+                    {"mean_len": {"title": "Mean length", "suffix": " bp"}},
+                ],
+                "data": general,
+            }),
+        ),
+        (
+            "gc",
+            linegraph_section("gc", "GC content", "GC (%)", "Reads", gc),
+        ),
+        (
+            "len",
+            linegraph_section("len", "Read length", "Length (bp)", "Reads", len),
+        ),
+    ];
+    if !qual.is_empty() {
+        sections.push((
+            "qual_by_position",
+            linegraph_section(
+                "qual_by_position",
+                "Mean quality by position",
+                "Position (bp)",
+                "Mean Phred score",
+                qual,
+            ),
+        ));
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::test_fixtures::options;
+    use crate::stats::ReadStats;
+
+    /// Reads the block style YAML subset written by `to_yaml`: nested block mappings and
+    /// sequences with flow scalars, following the YAML rules for it.
+    fn parse_yaml(yaml: &str) -> Value {
+        let body = yaml.strip_prefix("---\n").expect("missing document start");
+        let mut lines: Vec<(usize, String)> = body
+            .lines()
+            .map(|line| {
+                let content = line.trim_start_matches(' ');
+                (line.len() - content.len(), content.to_string())
+            })
+            .collect();
+        let mut pos = 0;
+        let value = parse_node(&mut lines, &mut pos, 0);
+        assert_eq!(pos, lines.len(), "unparsed line {:?}", lines.get(pos));
+        value
+    }
+
+    fn parse_node(lines: &mut [(usize, String)], pos: &mut usize, indent: usize) -> Value {
+        assert_eq!(
+            lines[*pos].0, indent,
+            "bad indentation at {:?}",
+            lines[*pos]
+        );
+        if lines[*pos].1.starts_with("- ") {
+            let mut items = Vec::new();
+            while *pos < lines.len() && lines[*pos].0 == indent && lines[*pos].1.starts_with("- ") {
+                let rest = lines[*pos].1[2..].to_string();
+                if rest.starts_with("- ") || split_key(&rest).is_some() {
+                    // A nested block starts on the line of the dash, as if indented past it
+                    lines[*pos] = (indent + 2, rest);
+                    items.push(parse_node(lines, pos, indent + 2));
+                } else {
+                    items.push(parse_scalar(&rest));
+                    *pos += 1;
+                }
+            }
+            Value::Array(items)
+        } else {
+            let mut map = Map::new();
+            while *pos < lines.len() && lines[*pos].0 == indent {
+                let line = lines[*pos].1.clone();
+                let (key, rest) = split_key(&line).expect("expected a mapping key");
+                *pos += 1;
+                let value = if rest.is_empty() {
+                    parse_node(lines, pos, indent + 2)
+                } else {
+                    parse_scalar(rest)
+                };
+                assert!(map.insert(key, value).is_none(), "duplicate key");
+            }
+            Value::Object(map)
+        }
+    }
+
+    /// Splits a mapping entry into its key and the rest of the line after `: `.
+    fn split_key(line: &str) -> Option<(String, &str)> {
+        let (key, after_key) = if line.starts_with('"') {
+            let mut stream = serde_json::Deserializer::from_str(line).into_iter::<String>();
+            let key = stream.next()?.ok()?;
+            (key, &line[stream.byte_offset()..])
+        } else {
+            let key_len = line.find(':')?;
+            (line[..key_len].to_string(), &line[key_len..])
+        };
+        match after_key.strip_prefix(':')? {
+            "" => Some((key, "")),
+            rest => rest.strip_prefix(' ').map(|rest| (key, rest)),
+        }
+    }
+
+    fn parse_scalar(scalar: &str) -> Value {
+        match scalar {
+            "[]" => Value::Array(Vec::new()),
+            "{}" => Value::Object(Map::new()),
+            // Double-quoted strings, numbers, booleans and null share the JSON syntax
+            other => serde_json::from_str(other).expect("unexpected scalar"),
+        }
+    }
+
+    #[test]
+    fn yaml_round_trips() {
+        let value = json!({
+            "total_records": 3,
+            "gc_distrib": {"40": 1, "55": 2},
+            "mean": 27.25,
+            "negative": -1.5e-12,
+            "flags": [true, false, null],
+            "empty_list": [],
+            "empty_map": {},
+            "name": "Illumina Small RNA 3' Adapter",
+            "tricky": "a: b # c\n\t\"quoted\" \\ - [x] {y} \u{7} ñ",
+            "key with spaces": "- not a list",
+            "": "empty key",
+            "nested": [[1, [2, 3]], [], {"a": [{"b": {}}, {"c": [4]}]}],
+            "records": [
+                {"position": 1, "counts": [0, 5], "name": "x"},
+                {"position": 2, "counts": [], "name": "true"},
+            ],
+        });
+        let yaml = to_yaml(&value);
+        assert_eq!(parse_yaml(&yaml), value, "{}", yaml);
+    }
+
+    #[test]
+    fn yaml_of_stats_round_trips() {
+        let options = options();
+        let mut stats = ReadStats::new();
+        for (seq, qual) in [
+            (&b"ACGTNNAGATCGGAAGAG"[..], &b"IIIII#####IIIIIIII"[..]),
+            (b"GGGCCC", b"!!!!!!"),
+            (b"GGGCCC", b"JJJJJJ"),
+        ] {
+            stats.add_read(seq, Some(qual), &options).unwrap();
+        }
+        stats.finish();
+        let value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(parse_yaml(&to_yaml(&value)), value);
+    }
+
+    #[test]
+    fn existing_tsv_files_are_found_before_writing() {
+        let dir = std::env::temp_dir().join(format!("seq_stats_output_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stem = dir.join("out").to_string_lossy().into_owned();
+        fs::write(format!("{}.gc_distrib.tsv", stem), "").unwrap();
+
+        let err = StatsOutput::create(&stem, OutputFormat::Tsv, false)
+            .err()
+            .expect("the TSV table would be overwritten");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        // The JSON output writes a single file
+        assert!(StatsOutput::create(&stem, OutputFormat::Json, false).is_ok());
+        let output = StatsOutput::create(&stem, OutputFormat::Tsv, true).unwrap();
+        output.write("sample", &json!({"total_reads": 1})).unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}.summary.tsv", stem)).unwrap(),
+            "metric\tvalue\ntotal_reads\t1\n"
+        );
+        // Only the written files are left, no temporary ones
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_output_directory_is_found_before_writing() {
+        let missing_dir =
+            std::env::temp_dir().join(format!("seq_stats_missing_{}", std::process::id()));
+        let fpath = missing_dir.join("out.json").to_string_lossy().into_owned();
+        assert!(StatsOutput::create(&fpath, OutputFormat::Json, false).is_err());
+    }
+}
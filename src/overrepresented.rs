@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Table size per unit of the minimum reported fraction,
+/// counts can then be overestimated by at most a tenth of the reporting threshold
+const CAPACITY_FACTOR: f64 = 10.0;
+
+struct Entry {
+    seq: Vec<u8>,
+    count: usize,
+    /// Upper bound of the overcount, inherited from the evicted sequence
+    error: usize,
+}
+
+/// Heavy hitters tracker (Space-Saving algorithm) over the read prefixes.
+/// Keeps a bounded table of sequences; when it is full a new sequence replaces the least
+/// counted one and inherits its count. Any sequence more frequent than 1/capacity of the
+/// reads is guaranteed to be in the table.
+/// The result depends on the read order, so it has to be fed the reads in input order.
+#[derive(Default)]
+pub struct OverrepTracker {
+    capacity: usize,
+    prefix_len: usize,
+    min_fraction: f64,
+    entries: Vec<Entry>,
+    index: HashMap<Vec<u8>, usize>,
+    /// Entries sorted by count, ties broken by insertion order
+    by_count: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OverrepresentedSeq {
+    pub sequence: String,
+    /// Exact if the sequence entered the table before it filled up, otherwise a lower bound
+    pub count: usize,
+    pub percentage: f64,
+}
+
+impl OverrepTracker {
+    /// Tracks the first `prefix_len` bases of each read, 0 means the whole read.
+    /// Sequences above `min_fraction` of the reads are reported.
+    pub fn new(min_fraction: f64, prefix_len: usize) -> Self {
+        OverrepTracker {
+            capacity: (CAPACITY_FACTOR / min_fraction).ceil() as usize,
+            prefix_len,
+            min_fraction,
+            ..Default::default()
+        }
+    }
+
+    /// Tracker holding the sequences of a summary, as read from a stats file, with their counts.
+    /// Merged with other trackers it keeps every sequence, and reports the ones above `min_fraction`.
+    pub fn from_summary(overrepresented: &[OverrepresentedSeq], min_fraction: f64) -> Self {
+        let entries: Vec<Entry> = overrepresented
+            .iter()
+            .map(|seq| Entry {
+                seq: seq.sequence.as_bytes().to_vec(),
+                count: seq.count,
+                error: 0,
+            })
+            .collect();
+        OverrepTracker {
+            capacity: usize::MAX,
+            prefix_len: 0,
+            min_fraction,
+            index: entries
+                .iter()
+                .enumerate()
+                .map(|(idx, entry)| (entry.seq.clone(), idx))
+                .collect(),
+            by_count: entries
+                .iter()
+                .enumerate()
+                .map(|(idx, entry)| (entry.count, idx))
+                .collect(),
+            entries,
+        }
+    }
+
+    pub fn add(&mut self, seq: &[u8]) {
+        let key = if self.prefix_len > 0 && seq.len() > self.prefix_len {
+            &seq[..self.prefix_len]
+        } else {
+            seq
+        };
+
+        if let Some(&idx) = self.index.get(key) {
+            let entry = &mut self.entries[idx];
+            self.by_count.remove(&(entry.count, idx));
+            entry.count += 1;
+            self.by_count.insert((entry.count, idx));
+        } else if self.entries.len() < self.capacity {
+            let idx = self.entries.len();
+            self.entries.push(Entry {
+                seq: key.to_vec(),
+                count: 1,
+                error: 0,
+            });
+            self.index.insert(key.to_vec(), idx);
+            self.by_count.insert((1, idx));
+        } else if let Some((min_count, idx)) = self.by_count.pop_first() {
+            let entry = &mut self.entries[idx];
+            self.index.remove(&entry.seq);
+            entry.seq = key.to_vec();
+            entry.count = min_count + 1;
+            entry.error = min_count;
+            self.index.insert(key.to_vec(), idx);
+            self.by_count.insert((entry.count, idx));
+        }
+    }
+
+    /// Adds the counts of another tracker, keeping the most counted sequences
+    /// if the table overflows.
+    pub fn merge(&mut self, other: &OverrepTracker) {
+        self.capacity = self.capacity.max(other.capacity);
+        self.prefix_len = self.prefix_len.max(other.prefix_len);
+        if self.min_fraction == 0.0 {
+            self.min_fraction = other.min_fraction;
+        }
+
+        let mut counts: HashMap<Vec<u8>, (usize, usize)> = self
+            .entries
+            .drain(..)
+            .map(|entry| (entry.seq, (entry.count, entry.error)))
+            .collect();
+        for entry in &other.entries {
+            let counts = counts.entry(entry.seq.clone()).or_insert((0, 0));
+            counts.0 += entry.count;
+            counts.1 += entry.error;
+        }
+        let mut merged: Vec<Entry> = counts
+            .into_iter()
+            .map(|(seq, (count, error))| Entry { seq, count, error })
+            .collect();
+        merged.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.seq.cmp(&b.seq)));
+        merged.truncate(self.capacity);
+
+        self.index = merged
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| (entry.seq.clone(), idx))
+            .collect();
+        self.by_count = merged
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| (entry.count, idx))
+            .collect();
+        self.entries = merged;
+    }
+
+    /// Sequences whose guaranteed count reaches the minimum fraction of `total_reads`,
+    /// most frequent first.
+    pub fn summarize(&self, total_reads: usize) -> Vec<OverrepresentedSeq> {
+        let min_count = (self.min_fraction * total_reads as f64).ceil().max(1.0) as usize;
+        let mut overrepresented: Vec<OverrepresentedSeq> = self
+            .entries
+            .iter()
+            .map(|entry| (entry, entry.count - entry.error))
+            .filter(|(_, count)| *count >= min_count)
+            .map(|(entry, count)| OverrepresentedSeq {
+                sequence: String::from_utf8_lossy(&entry.seq).into_owned(),
+                count,
+                percentage: count as f64 / total_reads as f64 * 100.0,
+            })
+            .collect();
+        overrepresented.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.sequence.cmp(&b.sequence))
+        });
+        overrepresented
+    }
+}
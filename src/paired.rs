@@ -0,0 +1,226 @@
+use crate::error::{ErrorKind, SeqStatsError};
+use crate::seq_io::{RecordPosition, SeqFormat, SeqReader, SeqRecord};
+use crate::validate::ValidationReport;
+
+/// Source of read pairs: two mate files read in lockstep, or one interleaved file
+pub enum PairReader {
+    TwoFiles(SeqReader, SeqReader),
+    Interleaved(SeqReader),
+}
+
+impl PairReader {
+    /// Pairs two mate readers, both must hold the same format.
+    pub fn from_two_files(
+        r1_reader: SeqReader,
+        r2_reader: SeqReader,
+    ) -> Result<Self, SeqStatsError> {
+        if r1_reader.format() != r2_reader.format() {
+            return Err(SeqStatsError::new(
+                ErrorKind::Parse,
+                format!(
+                    "R1 and R2 formats differ: {:?} (R1) vs {:?} (R2)",
+                    r1_reader.format(),
+                    r2_reader.format()
+                ),
+            )
+            .in_file(r2_reader.path()));
+        }
+        Ok(PairReader::TwoFiles(r1_reader, r2_reader))
+    }
+
+    /// Reader of the R1 reads, used to create records and writers of the right format.
+    pub fn r1_reader(&self) -> &SeqReader {
+        match self {
+            PairReader::TwoFiles(r1_reader, _) => r1_reader,
+            PairReader::Interleaved(reader) => reader,
+        }
+    }
+
+    /// Reads the next pair into a fragment of two records,
+    /// and checks that both mates belong to the same fragment.
+    /// Returns false once both inputs are exhausted.
+    /// `pair_idx` is the 1-based number of the pair, used in error messages.
+    pub fn read_pair(
+        &mut self,
+        fragment: &mut Fragment,
+        pair_idx: usize,
+    ) -> Result<bool, SeqStatsError> {
+        let ([r1, r2], [r1_position, r2_position]) =
+            (&mut fragment.records[..], &mut fragment.positions[..])
+        else {
+            unreachable!("read pairs have two mates");
+        };
+        let (has_r1, has_r2) = match self {
+            PairReader::TwoFiles(r1_reader, r2_reader) => {
+                let has_r1 = r1_reader.read(r1)?;
+                *r1_position = r1_reader.position();
+                let has_r2 = r2_reader.read(r2)?;
+                *r2_position = r2_reader.position();
+                fragment.valid = r1_reader.record_valid() && r2_reader.record_valid();
+                (has_r1, has_r2)
+            }
+            PairReader::Interleaved(reader) => {
+                let has_r1 = reader.read(r1)?;
+                *r1_position = reader.position();
+                let r1_valid = reader.record_valid();
+                let has_r2 = has_r1 && reader.read(r2)?;
+                *r2_position = reader.position();
+                fragment.valid = r1_valid && reader.record_valid();
+                (has_r1, has_r2)
+            }
+        };
+        let r2_reader = match &*self {
+            PairReader::TwoFiles(_, r2_reader) => r2_reader,
+            PairReader::Interleaved(reader) => reader,
+        };
+
+        match (has_r1, has_r2) {
+            (false, false) => Ok(false),
+            (true, true) => {
+                if mate_id(r1.id()) != mate_id(r2.id()) {
+                    return Err(r2_reader.error(
+                        ErrorKind::Parse,
+                        format!(
+                            "Read IDs out of sync at pair {}: '{}' (R1) vs '{}' (R2)",
+                            pair_idx,
+                            r1.id(),
+                            r2.id()
+                        ),
+                    ));
+                }
+                Ok(true)
+            }
+            (true, false) if matches!(self, PairReader::Interleaved(_)) => Err(r2_reader.error(
+                ErrorKind::Parse,
+                format!(
+                    "Interleaved input has an odd number of reads: read '{}' has no mate",
+                    r1.id()
+                ),
+            )),
+            (true, false) => Err(r2_reader.error(
+                ErrorKind::Parse,
+                format!(
+                    "R2 input ended before R1: R1 has more than {} reads, last R1 read '{}'",
+                    pair_idx - 1,
+                    r1.id()
+                ),
+            )),
+            (false, true) => Err(self.r1_reader().error(
+                ErrorKind::Parse,
+                format!(
+                    "R1 input ended before R2: R2 has more than {} reads, last R2 read '{}'",
+                    pair_idx - 1,
+                    r2.id()
+                ),
+            )),
+        }
+    }
+}
+
+/// Records of a fragment, one per mate, and where they were read
+#[derive(Clone)]
+pub struct Fragment {
+    pub records: Vec<SeqRecord>,
+    pub positions: Vec<RecordPosition>,
+    /// Every record passed the validation, or there is no validation
+    pub valid: bool,
+    /// Index of the input the fragment was read from, when there are several
+    pub input: usize,
+}
+
+/// Reads fragments: single reads, or read pairs.
+/// A fragment holds one record per mate.
+pub enum FragmentReader {
+    Single(SeqReader),
+    Paired(PairReader),
+}
+
+impl FragmentReader {
+    pub fn format(&self) -> SeqFormat {
+        match self {
+            FragmentReader::Single(reader) => reader.format(),
+            FragmentReader::Paired(pair_reader) => pair_reader.r1_reader().format(),
+        }
+    }
+
+    pub fn num_mates(&self) -> usize {
+        match self {
+            FragmentReader::Single(_) => 1,
+            FragmentReader::Paired(_) => 2,
+        }
+    }
+
+    /// Creates an empty fragment with records of the right format.
+    pub fn new_fragment(&self) -> Fragment {
+        let records = match self {
+            FragmentReader::Single(reader) => vec![reader.new_record()],
+            FragmentReader::Paired(pair_reader) => vec![
+                pair_reader.r1_reader().new_record(),
+                pair_reader.r1_reader().new_record(),
+            ],
+        };
+        Fragment {
+            positions: vec![RecordPosition::default(); records.len()],
+            records,
+            valid: true,
+            input: 0,
+        }
+    }
+
+    /// Reads the next fragment into `fragment`, that must come from `new_fragment`.
+    /// Returns false at the end of the input.
+    /// Fragments with a record that failed the validation are skipped.
+    /// `fragment_idx` is the 1-based number of the fragment, used in error messages.
+    pub fn read(
+        &mut self,
+        fragment: &mut Fragment,
+        fragment_idx: usize,
+    ) -> Result<bool, SeqStatsError> {
+        loop {
+            let has_fragment = match (&mut *self, &mut fragment.records[..]) {
+                (FragmentReader::Single(reader), [record]) => {
+                    let has_record = reader.read(record)?;
+                    fragment.positions[0] = reader.position();
+                    fragment.valid = reader.record_valid();
+                    has_record
+                }
+                (FragmentReader::Paired(pair_reader), [_, _]) => {
+                    pair_reader.read_pair(fragment, fragment_idx)?
+                }
+                _ => unreachable!("fragment size does not match the number of mates"),
+            };
+            if !has_fragment || fragment.valid {
+                return Ok(has_fragment);
+            }
+        }
+    }
+
+    /// Issues found by the validation in every input file
+    pub fn validation_report(&self) -> Option<ValidationReport> {
+        let mut reports = self
+            .readers()
+            .into_iter()
+            .filter_map(SeqReader::validation_report);
+        let mut report = reports.next()?;
+        reports.for_each(|other| report.merge(&other));
+        Some(report)
+    }
+
+    /// Reader of each input file
+    fn readers(&self) -> Vec<&SeqReader> {
+        match self {
+            FragmentReader::Single(reader) => vec![reader],
+            FragmentReader::Paired(PairReader::TwoFiles(r1_reader, r2_reader)) => {
+                vec![r1_reader, r2_reader]
+            }
+            FragmentReader::Paired(PairReader::Interleaved(reader)) => vec![reader],
+        }
+    }
+}
+
+/// Read ID without the old-style `/1` or `/2` mate suffix.
+fn mate_id(id: &str) -> &str {
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
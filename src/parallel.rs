@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Number of items sent to a worker thread at a time
+const BATCH_SIZE: usize = 4096;
+
+/// Error returned by the process function, it has to be sent back from the worker threads
+pub type ProcessError = Box<dyn Error + Send + Sync>;
+
+/// Function that folds an item, given with its 1-based number, into an accumulator
+type ProcessFn<T, S> = dyn Fn(&mut S, usize, &T) -> Result<(), ProcessError> + Send + Sync;
+
+struct Batch<T> {
+    /// 1-based number of the first item in the batch
+    first_idx: usize,
+    items: Vec<T>,
+}
+
+/// Error raised by a worker, `idx` is the 1-based number of the failing item
+struct ItemError {
+    idx: usize,
+    error: ProcessError,
+}
+
+enum Mode<T, S> {
+    Inline(S),
+    Threaded {
+        batch: Vec<T>,
+        sender: Option<SyncSender<Batch<T>>>,
+        workers: Vec<JoinHandle<Result<S, ItemError>>>,
+        failed: Arc<AtomicBool>,
+    },
+}
+
+/// Folds items into accumulators, either inline in the calling thread or in batches
+/// spread over worker threads, one accumulator per worker.
+/// The accumulators are returned by `finish` to be merged by the caller,
+/// so the merge has to be associative and commutative for the result not to depend on the number of threads.
+pub struct BatchProcessor<T, S> {
+    process: Arc<ProcessFn<T, S>>,
+    num_items: usize,
+    mode: Mode<T, S>,
+}
+
+impl<T: Send + 'static, S: Send + 'static> BatchProcessor<T, S> {
+    /// With one thread the items are processed inline, otherwise `n_threads` workers are spawned.
+    pub fn new(
+        n_threads: usize,
+        init: impl Fn() -> S,
+        process: impl Fn(&mut S, usize, &T) -> Result<(), ProcessError> + Send + Sync + 'static,
+    ) -> Self {
+        let process: Arc<ProcessFn<T, S>> = Arc::new(process);
+        if n_threads <= 1 {
+            return BatchProcessor {
+                process,
+                num_items: 0,
+                mode: Mode::Inline(init()),
+            };
+        }
+
+        let (sender, receiver) = sync_channel::<Batch<T>>(n_threads * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let failed = Arc::new(AtomicBool::new(false));
+        let workers = (0..n_threads)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let process = Arc::clone(&process);
+                let failed = Arc::clone(&failed);
+                let mut acc = init();
+                thread::spawn(move || {
+                    loop {
+                        let batch = match receiver.lock().unwrap().recv() {
+                            Ok(batch) => batch,
+                            Err(_) => break,
+                        };
+                        for (offset, item) in batch.items.iter().enumerate() {
+                            let idx = batch.first_idx + offset;
+                            if let Err(error) = process(&mut acc, idx, item) {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(ItemError { idx, error });
+                            }
+                        }
+                    }
+                    Ok(acc)
+                })
+            })
+            .collect();
+
+        BatchProcessor {
+            process,
+            num_items: 0,
+            mode: Mode::Threaded {
+                batch: Vec::with_capacity(BATCH_SIZE),
+                sender: Some(sender),
+                workers,
+                failed,
+            },
+        }
+    }
+
+    /// Processes an item, or queues it for the worker threads.
+    /// Fails as soon as a worker has failed, with the error of the earliest failing item.
+    pub fn add(&mut self, item: T) -> Result<(), Box<dyn Error>> {
+        self.num_items += 1;
+        match &mut self.mode {
+            Mode::Inline(acc) => {
+                (self.process)(acc, self.num_items, &item).map_err(|error| error as Box<dyn Error>)
+            }
+            Mode::Threaded {
+                batch,
+                sender,
+                workers,
+                failed,
+            } => {
+                batch.push(item);
+                if batch.len() < BATCH_SIZE {
+                    return Ok(());
+                }
+                let items = std::mem::replace(batch, Vec::with_capacity(BATCH_SIZE));
+                let batch = Batch {
+                    first_idx: self.num_items + 1 - items.len(),
+                    items,
+                };
+                let sent = match sender {
+                    Some(sender) if !failed.load(Ordering::Relaxed) => sender.send(batch).is_ok(),
+                    _ => false,
+                };
+                if !sent {
+                    // Some worker has failed, joining them gets its error
+                    join_workers(sender.take(), std::mem::take(workers))?;
+                    return Err("stats worker threads stopped unexpectedly".into());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for the pending items to be processed and returns the accumulators.
+    pub fn finish(mut self) -> Result<Vec<S>, Box<dyn Error>> {
+        if let Mode::Threaded { batch, sender, .. } = &mut self.mode {
+            if !batch.is_empty() {
+                let items = std::mem::take(batch);
+                let first_idx = self.num_items + 1 - items.len();
+                if let Some(sender) = sender {
+                    // A send error means that every worker has failed, join_workers reports it
+                    let _ = sender.send(Batch { first_idx, items });
+                }
+            }
+        }
+        match self.mode {
+            Mode::Inline(acc) => Ok(vec![acc]),
+            Mode::Threaded {
+                sender, workers, ..
+            } => join_workers(sender, workers),
+        }
+    }
+}
+
+/// Closes the batch channel and collects the accumulators of the workers.
+/// If any worker failed, returns the error of the earliest failing item.
+fn join_workers<T, S>(
+    sender: Option<SyncSender<Batch<T>>>,
+    workers: Vec<JoinHandle<Result<S, ItemError>>>,
+) -> Result<Vec<S>, Box<dyn Error>> {
+    // Dropping the sender lets the workers exit once the channel is drained
+    drop(sender);
+
+    let mut accs = Vec::with_capacity(workers.len());
+    let mut first_error: Option<ItemError> = None;
+    for worker in workers {
+        match worker.join().expect("stats worker thread panicked") {
+            Ok(acc) => accs.push(acc),
+            Err(error) => {
+                if first_error
+                    .as_ref()
+                    .is_none_or(|first| error.idx < first.idx)
+                {
+                    first_error = Some(error);
+                }
+            }
+        }
+    }
+    match first_error {
+        Some(error) => Err(error.error as Box<dyn Error>),
+        None => Ok(accs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::test_fixtures::{options, random_reads};
+    use crate::stats::ReadStats;
+
+    fn threaded_stats(reads: &[(Vec<u8>, Vec<u8>)], n_threads: usize) -> serde_json::Value {
+        let options = options();
+        let mut processor = BatchProcessor::new(n_threads, ReadStats::new, {
+            let options = options.clone();
+            move |stats: &mut ReadStats, _, (seq, qual): &(Vec<u8>, Vec<u8>)| {
+                Ok(stats.add_read(seq, Some(qual), &options)?)
+            }
+        });
+        for read in reads {
+            processor.add(read.clone()).unwrap();
+        }
+        let mut stats = ReadStats::new();
+        for partial_stats in processor.finish().unwrap() {
+            stats.merge(&partial_stats);
+        }
+        stats.finish();
+        serde_json::to_value(&stats).unwrap()
+    }
+
+    #[test]
+    fn threaded_stats_equal_inline_ones() {
+        let reads = random_reads(3 * BATCH_SIZE + 100);
+        let inline_stats = threaded_stats(&reads, 1);
+        assert_eq!(inline_stats["total_records"], 3 * BATCH_SIZE + 100);
+        for n_threads in [2, 4] {
+            assert_eq!(threaded_stats(&reads, n_threads), inline_stats);
+        }
+    }
+
+    #[test]
+    fn earliest_failing_item_is_reported() {
+        for n_threads in [1, 3] {
+            let mut processor = BatchProcessor::new(
+                n_threads,
+                || 0,
+                |sum: &mut usize, idx, item: &usize| {
+                    if *item % 5000 == 4999 {
+                        return Err(format!("item {} failed", idx).into());
+                    }
+                    *sum += item;
+                    Ok(())
+                },
+            );
+            let result = (0..20_000).try_for_each(|item| processor.add(item));
+            let error = result
+                .and_then(|_| processor.finish().map(|_| ()))
+                .unwrap_err();
+            assert_eq!(error.to_string(), "item 5000 failed");
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::stats::{FragmentStats, ReadStats};
+
+/// Exit status of the process when a QC check fails
+pub const QC_FAIL_EXIT_CODE: i32 = 3;
+
+/// Limits of a metric, a value beyond a fail limit fails, beyond a warn limit warns
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub warn_below: Option<f64>,
+    pub fail_below: Option<f64>,
+    pub warn_above: Option<f64>,
+    pub fail_above: Option<f64>,
+}
+
+/// Limits per metric, read from a JSON file like:
+/// `{"total_reads": {"fail_below": 100000}, "gc_deviation": {"warn_above": 15, "fail_above": 30}}`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QcThresholds {
+    /// Reads, or read pairs in paired-end mode
+    pub total_reads: Option<Limits>,
+    /// Mean Phred score of all the bases
+    pub mean_quality: Option<Limits>,
+    /// Deviation percentage of the GC distribution from the fitted normal one
+    pub gc_deviation: Option<Limits>,
+    /// Highest percentage of reads with any adapter
+    pub adapter_content: Option<Limits>,
+    /// Percentage of duplicated reads, the ones removed by deduplication
+    pub duplication: Option<Limits>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+pub struct QcCheck {
+    pub metric: &'static str,
+    /// Mate checked in paired-end mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mate: Option<&'static str>,
+    pub value: f64,
+    pub status: Status,
+}
+
+/// Worst status of the checks and every check.
+/// Metrics not available, like the quality of FASTA reads, are not checked.
+#[derive(Serialize)]
+pub struct QcStatus {
+    pub status: Status,
+    pub checks: Vec<QcCheck>,
+}
+
+impl Limits {
+    fn status(&self, value: f64) -> Status {
+        let beyond = |below: Option<f64>, above: Option<f64>| {
+            below.is_some_and(|limit| value < limit) || above.is_some_and(|limit| value > limit)
+        };
+        if beyond(self.fail_below, self.fail_above) {
+            Status::Fail
+        } else if beyond(self.warn_below, self.warn_above) {
+            Status::Warn
+        } else {
+            Status::Pass
+        }
+    }
+}
+
+fn mean_quality(stats: &ReadStats) -> Option<f64> {
+    let (sum, count) = stats
+        .qual_by_position
+        .iter()
+        .fold((0.0, 0), |(sum, count), position| {
+            (
+                sum + position.mean * position.count as f64,
+                count + position.count,
+            )
+        });
+    (count > 0).then(|| sum / count as f64)
+}
+
+fn adapter_content(stats: &ReadStats) -> Option<f64> {
+    stats
+        .adapter_content
+        .iter()
+        .filter_map(|adapter| adapter.cumulative_percent_by_position.last().copied())
+        .reduce(f64::max)
+}
+
+impl QcThresholds {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader)
+            .map_err(|err| format!("invalid QC thresholds file {}: {}", path, err).into())
+    }
+
+    fn check_mate(&self, mate: Option<&'static str>, stats: &ReadStats, checks: &mut Vec<QcCheck>) {
+        let metrics: [(&'static str, &Option<Limits>, Option<f64>); 5] = [
+            (
+                "total_reads",
+                &self.total_reads,
+                Some(stats.total_records as f64),
+            ),
+            ("mean_quality", &self.mean_quality, mean_quality(stats)),
+            (
+                "gc_deviation",
+                &self.gc_deviation,
+                stats
+                    .gc_theoretical
+                    .as_ref()
+                    .map(|gc| gc.comparison.deviation_percent),
+            ),
+            (
+                "adapter_content",
+                &self.adapter_content,
+                adapter_content(stats),
+            ),
+            (
+                "duplication",
+                &self.duplication,
+                stats
+                    .duplication
+                    .as_ref()
+                    .map(|duplication| 100.0 - duplication.percent_remaining_after_dedup),
+            ),
+        ];
+        for (metric, limits, value) in metrics {
+            if let (Some(limits), Some(value)) = (limits, value) {
+                checks.push(QcCheck {
+                    metric,
+                    mate,
+                    value: (value * 100.0).round() / 100.0,
+                    status: limits.status(value),
+                });
+            }
+        }
+    }
+
+    /// Checks single reads, or each mate of read pairs.
+    pub fn check(&self, stats: &FragmentStats) -> QcStatus {
+        let mut checks = Vec::new();
+        match stats {
+            FragmentStats::Single(stats) => self.check_mate(None, stats, &mut checks),
+            FragmentStats::Paired(stats) => {
+                self.check_mate(Some("r1"), &stats.r1, &mut checks);
+                self.check_mate(Some("r2"), &stats.r2, &mut checks);
+            }
+        }
+        QcStatus {
+            status: checks
+                .iter()
+                .map(|check| check.status)
+                .max()
+                .unwrap_or(Status::Pass),
+            checks,
+        }
+    }
+}
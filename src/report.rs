@@ -0,0 +1,450 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::atomic_file::{write_atomically, AtomicFile};
+use crate::output::{histogram_mean, mate_stats};
+
+const PLOT_WIDTH: f64 = 720.0;
+const PLOT_HEIGHT: f64 = 360.0;
+const MARGIN_LEFT: f64 = 64.0;
+const MARGIN_RIGHT: f64 = 150.0;
+const MARGIN_TOP: f64 = 36.0;
+const MARGIN_BOTTOM: f64 = 52.0;
+const NUM_TICKS: f64 = 5.0;
+
+const COLORS: [&str; 6] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#7f7f7f", "#9467bd",
+];
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 960px; color: #222; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; }
+th, td { padding: 0.3em 1em; border-bottom: 1px solid #ddd; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+svg { display: block; margin: 1em 0; }
+svg text { font-family: sans-serif; font-size: 12px; fill: #222; }
+svg .title { font-size: 14px; font-weight: bold; }
+svg .grid { stroke: #e5e5e5; }
+svg .axis { stroke: #222; }
+";
+
+struct Series {
+    name: String,
+    points: Vec<(f64, f64)>,
+}
+
+/// Shaded area between two lines sharing their x values
+struct Band {
+    name: String,
+    lower: Vec<(f64, f64)>,
+    upper: Vec<(f64, f64)>,
+}
+
+struct LinePlot {
+    title: String,
+    xlab: &'static str,
+    ylab: &'static str,
+    bands: Vec<Band>,
+    lines: Vec<Series>,
+}
+
+/// Step between axis ticks rounded to 1, 2 or 5 times a power of 10
+fn nice_step(range: f64) -> f64 {
+    let raw_step = range / NUM_TICKS;
+    let magnitude = 10f64.powf(raw_step.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .find(|factor| factor * magnitude >= raw_step)
+        .unwrap_or(10.0);
+    step * magnitude
+}
+
+fn format_tick(value: f64, step: f64) -> String {
+    if step >= 1.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.1$}", value, (-step.log10().floor()) as usize)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl LinePlot {
+    fn new(title: String, xlab: &'static str, ylab: &'static str) -> Self {
+        LinePlot {
+            title,
+            xlab,
+            ylab,
+            bands: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    fn points(&self) -> impl Iterator<Item = &(f64, f64)> {
+        self.lines.iter().flat_map(|line| &line.points).chain(
+            self.bands
+                .iter()
+                .flat_map(|band| band.lower.iter().chain(&band.upper)),
+        )
+    }
+
+    /// Axis range, starting at zero for the y axis
+    fn ranges(&self) -> ((f64, f64), (f64, f64)) {
+        let (mut x_min, mut x_max, mut y_max) = (f64::MAX, f64::MIN, 0f64);
+        for &(x, y) in self.points() {
+            x_min = x_min.min(x);
+            x_max = x_max.max(x);
+            y_max = y_max.max(y);
+        }
+        if x_min > x_max {
+            (x_min, x_max) = (0.0, 1.0);
+        }
+        if x_max - x_min < 1.0 {
+            (x_min, x_max) = (x_min - 1.0, x_max + 1.0);
+        }
+        if y_max <= 0.0 {
+            y_max = 1.0;
+        }
+        ((x_min, x_max), (0.0, y_max * 1.05))
+    }
+
+    fn to_svg(&self) -> String {
+        let ((x_min, x_max), (y_min, y_max)) = self.ranges();
+        let inner_width = PLOT_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+        let inner_height = PLOT_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        let to_x = |x: f64| MARGIN_LEFT + (x - x_min) / (x_max - x_min) * inner_width;
+        let to_y =
+            |y: f64| MARGIN_TOP + inner_height - (y - y_min) / (y_max - y_min) * inner_height;
+        let path = |points: &[(f64, f64)]| -> String {
+            points
+                .iter()
+                .map(|&(x, y)| format!("{:.1},{:.1}", to_x(x), to_y(y)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+            w = PLOT_WIDTH,
+            h = PLOT_HEIGHT
+        );
+        svg.push_str(&format!(
+            "<text class=\"title\" x=\"{:.1}\" y=\"20\" text-anchor=\"middle\">{}</text>\n",
+            MARGIN_LEFT + inner_width / 2.0,
+            escape_html(&self.title)
+        ));
+
+        // grid and tick labels
+        let x_step = nice_step(x_max - x_min);
+        let mut x = (x_min / x_step).ceil() * x_step;
+        while x <= x_max {
+            svg.push_str(&format!(
+                "<line class=\"grid\" x1=\"{x:.1}\" y1=\"{:.1}\" x2=\"{x:.1}\" y2=\"{:.1}\"/>\n\
+                 <text x=\"{x:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+                MARGIN_TOP,
+                MARGIN_TOP + inner_height,
+                MARGIN_TOP + inner_height + 16.0,
+                format_tick(x, x_step),
+                x = to_x(x),
+            ));
+            x += x_step;
+        }
+        let y_step = nice_step(y_max - y_min);
+        let mut y = y_min;
+        while y <= y_max {
+            svg.push_str(&format!(
+                "<line class=\"grid\" x1=\"{:.1}\" y1=\"{y:.1}\" x2=\"{:.1}\" y2=\"{y:.1}\"/>\n\
+                 <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+                MARGIN_LEFT,
+                MARGIN_LEFT + inner_width,
+                MARGIN_LEFT - 6.0,
+                to_y(y) + 4.0,
+                format_tick(y, y_step),
+                y = to_y(y),
+            ));
+            y += y_step;
+        }
+
+        // axes and their labels
+        svg.push_str(&format!(
+            "<polyline class=\"axis\" fill=\"none\" points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\"/>\n",
+            MARGIN_LEFT,
+            MARGIN_TOP,
+            MARGIN_LEFT,
+            MARGIN_TOP + inner_height,
+            MARGIN_LEFT + inner_width,
+            MARGIN_TOP + inner_height
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+            MARGIN_LEFT + inner_width / 2.0,
+            PLOT_HEIGHT - 12.0,
+            self.xlab
+        ));
+        svg.push_str(&format!(
+            "<text x=\"16\" y=\"{y:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 16 {y:.1})\">{}</text>\n",
+            self.ylab,
+            y = MARGIN_TOP + inner_height / 2.0
+        ));
+
+        // data, bands first so that the lines are drawn over them
+        let mut legend = Vec::new();
+        for (idx, band) in self.bands.iter().enumerate() {
+            let color = COLORS[idx % COLORS.len()];
+            let outline: Vec<(f64, f64)> = band
+                .lower
+                .iter()
+                .chain(band.upper.iter().rev())
+                .copied()
+                .collect();
+            svg.push_str(&format!(
+                "<polygon fill=\"{}\" fill-opacity=\"0.2\" stroke=\"none\" points=\"{}\"/>\n",
+                color,
+                path(&outline)
+            ));
+            legend.push((band.name.as_str(), color, true));
+        }
+        for (idx, line) in self.lines.iter().enumerate() {
+            let color = COLORS[(idx + self.bands.len()) % COLORS.len()];
+            if line.points.len() == 1 {
+                let (x, y) = line.points[0];
+                svg.push_str(&format!(
+                    "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"{}\"/>\n",
+                    to_x(x),
+                    to_y(y),
+                    color
+                ));
+            } else {
+                svg.push_str(&format!(
+                    "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n",
+                    color,
+                    path(&line.points)
+                ));
+            }
+            legend.push((line.name.as_str(), color, false));
+        }
+
+        for (idx, (name, color, is_band)) in legend.into_iter().enumerate() {
+            let x = MARGIN_LEFT + inner_width + 12.0;
+            let y = MARGIN_TOP + 8.0 + idx as f64 * 18.0;
+            let key = if is_band {
+                format!(
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"18\" height=\"10\" fill=\"{}\" fill-opacity=\"0.2\"/>",
+                    x,
+                    y - 5.0,
+                    color
+                )
+            } else {
+                format!(
+                    "<line x1=\"{x:.1}\" y1=\"{y:.1}\" x2=\"{:.1}\" y2=\"{y:.1}\" stroke=\"{}\" stroke-width=\"2\"/>",
+                    x + 18.0,
+                    color
+                )
+            };
+            svg.push_str(&format!(
+                "{}\n<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+                key,
+                x + 24.0,
+                y + 4.0,
+                escape_html(name)
+            ));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Points of a histogram serialized as a map from numbers to counts
+fn histogram_points(histogram: &Value) -> Vec<(f64, f64)> {
+    let Some(histogram) = histogram.as_object() else {
+        return Vec::new();
+    };
+    histogram
+        .iter()
+        .filter_map(|(key, count)| Some((key.parse().ok()?, count.as_f64()?)))
+        .collect()
+}
+
+/// Values of a field of each per position record
+fn position_points(positions: &[Value], field: &str) -> Vec<(f64, f64)> {
+    positions
+        .iter()
+        .filter_map(|position| Some((position["position"].as_f64()?, position[field].as_f64()?)))
+        .collect()
+}
+
+fn qual_plot(name: &str, stats: &Value) -> Option<LinePlot> {
+    let positions = stats["qual_by_position"].as_array()?;
+    let mut plot = LinePlot::new(
+        format!("Quality by position: {}", name),
+        "Position (bp)",
+        "Phred score",
+    );
+    plot.bands.push(Band {
+        name: "10-90%".to_string(),
+        lower: position_points(positions, "p10"),
+        upper: position_points(positions, "p90"),
+    });
+    plot.bands.push(Band {
+        name: "25-75%".to_string(),
+        lower: position_points(positions, "p25"),
+        upper: position_points(positions, "p75"),
+    });
+    for field in ["mean", "median"] {
+        plot.lines.push(Series {
+            name: field.to_string(),
+            points: position_points(positions, field),
+        });
+    }
+    Some(plot)
+}
+
+fn composition_plot(name: &str, stats: &Value) -> Option<LinePlot> {
+    let positions = stats["base_composition_by_position"].as_array()?;
+    let count_fields = ["a", "c", "g", "t", "n", "ambiguous", "other"];
+    let totals: Vec<f64> = positions
+        .iter()
+        .map(|position| {
+            count_fields
+                .iter()
+                .filter_map(|field| position[*field].as_f64())
+                .sum()
+        })
+        .collect();
+    let mut plot = LinePlot::new(
+        format!("Base composition by position: {}", name),
+        "Position (bp)",
+        "Bases (%)",
+    );
+    for field in ["a", "c", "g", "t", "n"] {
+        let points = positions
+            .iter()
+            .zip(&totals)
+            .filter(|(_, &total)| total > 0.0)
+            .filter_map(|(position, total)| {
+                Some((
+                    position["position"].as_f64()?,
+                    position[field].as_f64()? / total * 100.0,
+                ))
+            })
+            .collect();
+        plot.lines.push(Series {
+            name: field.to_uppercase(),
+            points,
+        });
+    }
+    Some(plot)
+}
+
+fn format_optional(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| format!("{:.2}", value))
+}
+
+fn summary_table(mates: &[(String, &Value)]) -> String {
+    let mut html = String::from(
+        "<table>\n<tr><th>Sample</th><th>Reads</th><th>Mean GC (%)</th><th>GC deviation from normal (%)</th>\
+         <th>Mean length (bp)</th><th>Remaining after deduplication (%)</th></tr>\n",
+    );
+    for (name, stats) in mates {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(name),
+            stats["total_records"],
+            format_optional(histogram_mean(&stats["gc_distrib"])),
+            format_optional(stats["gc_theoretical"]["deviation_percent"].as_f64()),
+            format_optional(histogram_mean(&stats["len_distrib"])),
+            format_optional(stats["duplication"]["percent_remaining_after_dedup"].as_f64()),
+        ));
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Single file HTML report, with no external resources, with the plots of the
+/// GC and length distributions, and of the quality and base composition by position.
+fn html_report<T: Serialize>(
+    sample_name: &str,
+    stats: &T,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let value = serde_json::to_value(stats)?;
+    let mates = mate_stats(&value, sample_name);
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>seq_stats: {name}</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>seq_stats: {name}</h1>\n",
+        STYLE,
+        name = escape_html(sample_name)
+    );
+    if value.get("partial").is_some() {
+        html.push_str("<p><strong>Partial stats: the reading stopped before the end of the input.</strong></p>\n");
+    }
+    html.push_str("<h2>Summary</h2>\n");
+    html.push_str(&summary_table(&mates));
+
+    let mut gc_plot = LinePlot::new("GC content".to_string(), "GC (%)", "Reads");
+    let mut len_plot = LinePlot::new("Read length".to_string(), "Length (bp)", "Reads");
+    for (name, stats) in &mates {
+        gc_plot.lines.push(Series {
+            name: name.clone(),
+            points: histogram_points(&stats["gc_distrib"]),
+        });
+        gc_plot.lines.push(Series {
+            name: format!("{} (normal fit)", name),
+            points: histogram_points(&stats["gc_theoretical"]["expected_distrib"]),
+        });
+        if stats.get("gc_reference").is_some() {
+            gc_plot.lines.push(Series {
+                name: format!("{} (expected)", name),
+                points: histogram_points(&stats["gc_reference"]["expected_distrib"]),
+            });
+        }
+        len_plot.lines.push(Series {
+            name: name.clone(),
+            points: histogram_points(&stats["len_distrib"]),
+        });
+    }
+    html.push_str("<h2>GC content</h2>\n");
+    html.push_str(&gc_plot.to_svg());
+    html.push_str("<h2>Read length</h2>\n");
+    html.push_str(&len_plot.to_svg());
+
+    let qual_plots: Vec<LinePlot> = mates
+        .iter()
+        .filter_map(|(name, stats)| qual_plot(name, stats))
+        .collect();
+    if !qual_plots.is_empty() {
+        html.push_str("<h2>Quality by position</h2>\n");
+        for plot in qual_plots {
+            html.push_str(&plot.to_svg());
+        }
+    }
+
+    html.push_str("<h2>Base composition by position</h2>\n");
+    for plot in mates
+        .iter()
+        .filter_map(|(name, stats)| composition_plot(name, stats))
+    {
+        html.push_str(&plot.to_svg());
+    }
+
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Writes the report into `file`, created up front, and renames it into place.
+pub fn write_html_report<T: Serialize>(
+    file: AtomicFile,
+    sample_name: &str,
+    stats: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let html = html_report(sample_name, stats)?;
+    write_atomically(file, |writer| Ok(writer.write_all(html.as_bytes())?))
+}
@@ -0,0 +1,106 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+/// How the fragments are sampled
+#[derive(Clone, Copy)]
+pub enum SamplingMethod {
+    /// Each fragment is kept with this probability
+    Fraction(f64),
+    /// Uniform sample of this many fragments
+    Count(usize),
+}
+
+pub struct SamplingOptions {
+    pub method: SamplingMethod,
+    pub seed: u64,
+    /// Calculate the input stats on every fragment instead of only on the sampled ones
+    pub stats_on_full_input: bool,
+}
+
+#[derive(Serialize)]
+pub struct SamplingStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    pub seed: u64,
+    pub fragments_seen: usize,
+    pub fragments_sampled: usize,
+    pub stats_on_full_input: bool,
+}
+
+impl SamplingStats {
+    pub fn new(options: &SamplingOptions) -> Self {
+        let (fraction, count) = match options.method {
+            SamplingMethod::Fraction(fraction) => (Some(fraction), None),
+            SamplingMethod::Count(count) => (None, Some(count)),
+        };
+        SamplingStats {
+            fraction,
+            count,
+            seed: options.seed,
+            fragments_seen: 0,
+            fragments_sampled: 0,
+            stats_on_full_input: options.stats_on_full_input,
+        }
+    }
+}
+
+/// Keeps each item with a fixed probability, reproducible for a given seed.
+pub struct FractionSampler {
+    fraction: f64,
+    rng: StdRng,
+}
+
+impl FractionSampler {
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        FractionSampler {
+            fraction,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn keep(&mut self) -> bool {
+        self.rng.gen::<f64>() < self.fraction
+    }
+}
+
+/// Uniform sample of a fixed number of items of a stream of unknown length (Algorithm R).
+/// The items are kept with their index so they can be returned in input order.
+pub struct ReservoirSampler<T> {
+    capacity: usize,
+    num_seen: usize,
+    rng: StdRng,
+    reservoir: Vec<(usize, T)>,
+}
+
+impl<T: Clone> ReservoirSampler<T> {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        ReservoirSampler {
+            capacity,
+            num_seen: 0,
+            rng: StdRng::seed_from_u64(seed),
+            reservoir: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, idx: usize, item: &T) {
+        self.num_seen += 1;
+        if self.reservoir.len() < self.capacity {
+            self.reservoir.push((idx, item.clone()));
+            return;
+        }
+        let slot = self.rng.gen_range(0..self.num_seen);
+        if let Some((kept_idx, kept_item)) = self.reservoir.get_mut(slot) {
+            *kept_idx = idx;
+            kept_item.clone_from(item);
+        }
+    }
+
+    /// Sampled items sorted by input order
+    pub fn into_sorted(mut self) -> Vec<(usize, T)> {
+        self.reservoir.sort_by_key(|(idx, _)| *idx);
+        self.reservoir
+    }
+}
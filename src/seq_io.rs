@@ -0,0 +1,610 @@
+use bio::io::fasta::{self, FastaRead};
+use bio::io::fastq::{self, Error as FastqError, FastqRead};
+use flate2::read::MultiGzDecoder;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::error::{self, SeqStatsError};
+use crate::gzip::{self, ThreadedGzReader};
+use crate::validate::{FastqValidator, ValidateOptions, ValidationReport};
+
+/// Compression formats recognized by their magic bytes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+fn detect_compression(buffer: &[u8]) -> Compression {
+    if buffer.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if buffer.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Compression::Zstd
+    } else if buffer.starts_with(b"BZh") {
+        Compression::Bzip2
+    } else if buffer.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Compression::Xz
+    } else {
+        Compression::None
+    }
+}
+
+/// Bytes read so far from an input file, before decompression.
+/// The count is shared with the reader, that may run in another thread.
+#[derive(Clone)]
+pub struct ReadProgress {
+    bytes_read: Arc<AtomicU64>,
+    /// None for stdin
+    file_size: Option<u64>,
+}
+
+impl ReadProgress {
+    /// Progress of a file not opened yet, to be passed to `SeqReader::open`.
+    /// `"-"` means stdin, its size is unknown.
+    pub fn new(path: &str) -> Result<Self, SeqStatsError> {
+        let file_size = if path == "-" {
+            None
+        } else {
+            let metadata = fs::metadata(path).map_err(|err| {
+                SeqStatsError::new(error::ErrorKind::Io, err.to_string()).in_file(path)
+            })?;
+            Some(metadata.len())
+        };
+        Ok(ReadProgress {
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            file_size,
+        })
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn file_size(&self) -> Option<u64> {
+        self.file_size
+    }
+}
+
+/// Counts the bytes read from the inner reader
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.inner.read(buf)?;
+        self.bytes_read
+            .fetch_add(num_bytes as u64, Ordering::Relaxed);
+        Ok(num_bytes)
+    }
+}
+
+/// Wraps the errors of a decompressor, so that they are told apart from the parser ones
+#[derive(Debug)]
+struct DecompressionFailure(io::Error);
+
+impl fmt::Display for DecompressionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for DecompressionFailure {}
+
+/// Reports every error of the inner decompressor as a `DecompressionFailure`.
+/// Its kind is `InvalidData`, as the FASTQ parser takes `UnexpectedEof` as the end of the input.
+struct DecompressedReader<R> {
+    inner: R,
+}
+
+impl<R: Read> Read for DecompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner
+            .read(buf)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, DecompressionFailure(err)))
+    }
+}
+
+/// Kind of a reading error: decompression, parse, as the FASTA parser reports
+/// malformed records as `Other` I/O errors, or I/O.
+fn read_error_kind(err: &io::Error) -> error::ErrorKind {
+    if err
+        .get_ref()
+        .is_some_and(|inner| inner.is::<DecompressionFailure>())
+    {
+        error::ErrorKind::Decompression
+    } else if err.kind() == ErrorKind::Other {
+        error::ErrorKind::Parse
+    } else {
+        error::ErrorKind::Io
+    }
+}
+
+/// Opens a file or stdin, and decompresses if compressed with gzip, zstd, bzip2 or xz.
+/// zstd, bzip2 and xz support depend on the cargo features of the same names.
+/// With more than one thread gzip is decompressed in background threads,
+/// BGZF blocks are inflated in parallel by `n_threads` threads.
+/// `"-"` means read from stdin.
+/// `progress` counts the bytes read from the file, before decompression,
+/// for threaded gzip only the ones behind the decompressed data read so far.
+pub fn open_maybe_compressed(
+    path: &str,
+    n_threads: usize,
+    progress: &ReadProgress,
+) -> Result<Box<dyn Read>, SeqStatsError> {
+    let io_error =
+        |err: io::Error| SeqStatsError::new(error::ErrorKind::Io, err.to_string()).in_file(path);
+    let raw_input: Box<dyn Read + Send> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).map_err(io_error)?)
+    };
+
+    let mut buf_reader = BufReader::new(raw_input);
+
+    // Use fill_buf to peek at the buffer without consuming bytes
+    let buffer = buf_reader.fill_buf().map_err(io_error)?;
+    let compression = detect_compression(buffer);
+    let is_bgzf = gzip::is_bgzf(buffer);
+    let consumed_input = Arc::clone(&progress.bytes_read);
+    // The decompression threads read far ahead, the threaded reader counts the bytes behind its output
+    if compression == Compression::Gzip && n_threads > 1 {
+        let decoder = if is_bgzf {
+            ThreadedGzReader::bgzf(buf_reader, n_threads, consumed_input)
+        } else {
+            ThreadedGzReader::read_ahead(buf_reader, consumed_input)
+        };
+        return Ok(Box::new(DecompressedReader { inner: decoder }));
+    }
+    // The bytes are counted as they leave the buffer that holds the peeked ones
+    let counted_input: RawReader = BufReader::new(Box::new(CountingReader {
+        inner: buf_reader,
+        bytes_read: consumed_input,
+    }));
+
+    // Now wrap in a decoder or not
+    let decoder = match compression {
+        Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(counted_input)) as Box<dyn Read>),
+        Compression::Zstd => zstd_decoder(counted_input),
+        Compression::Bzip2 => bzip2_decoder(counted_input),
+        Compression::Xz => xz_decoder(counted_input),
+        Compression::None => return Ok(Box::new(counted_input)),
+    }
+    .map_err(|err| {
+        SeqStatsError::new(error::ErrorKind::Decompression, err.to_string()).in_file(path)
+    })?;
+    Ok(Box::new(DecompressedReader { inner: decoder }))
+}
+
+type RawReader = BufReader<Box<dyn Read + Send>>;
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder(reader: RawReader) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder(_reader: RawReader) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    Err(missing_feature_error("zstd"))
+}
+
+#[cfg(feature = "bzip2")]
+fn bzip2_decoder(reader: RawReader) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_decoder(_reader: RawReader) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    Err(missing_feature_error("bzip2"))
+}
+
+#[cfg(feature = "xz")]
+fn xz_decoder(reader: RawReader) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)))
+}
+
+#[cfg(not(feature = "xz"))]
+fn xz_decoder(_reader: RawReader) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+    Err(missing_feature_error("xz"))
+}
+
+#[cfg(not(all(feature = "zstd", feature = "bzip2", feature = "xz")))]
+fn missing_feature_error(compression: &str) -> Box<dyn std::error::Error> {
+    format!(
+        "Input is {} compressed, but seq_stats was built without the `{}` feature",
+        compression, compression
+    )
+    .into()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SeqFormat {
+    Fasta,
+    Fastq,
+}
+
+/// Sniffs the format from the first non-whitespace byte of the (decompressed) input.
+/// An empty input is taken as FASTQ.
+fn detect_format(reader: &mut impl BufRead) -> Result<SeqFormat, Box<dyn std::error::Error>> {
+    // Use fill_buf to peek at the buffer without consuming bytes
+    let buffer = reader.fill_buf()?;
+    match buffer.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b'>') => Ok(SeqFormat::Fasta),
+        Some(b'@') | None => Ok(SeqFormat::Fastq),
+        Some(&other) => Err(format!(
+            "Unrecognized sequence format: input starts with '{}', expected '>' (FASTA) or '@' (FASTQ)",
+            other as char
+        )
+        .into()),
+    }
+}
+
+/// A FASTA or FASTQ record
+#[derive(Clone)]
+pub enum SeqRecord {
+    Fasta(fasta::Record),
+    Fastq(fastq::Record),
+}
+
+impl SeqRecord {
+    pub fn id(&self) -> &str {
+        match self {
+            SeqRecord::Fasta(record) => record.id(),
+            SeqRecord::Fastq(record) => record.id(),
+        }
+    }
+
+    pub fn seq(&self) -> &[u8] {
+        match self {
+            SeqRecord::Fasta(record) => record.seq(),
+            SeqRecord::Fastq(record) => record.seq(),
+        }
+    }
+
+    /// Quality string, FASTA records have none
+    pub fn qual(&self) -> Option<&[u8]> {
+        match self {
+            SeqRecord::Fasta(_) => None,
+            SeqRecord::Fastq(record) => Some(record.qual()),
+        }
+    }
+
+    /// Copy of the record keeping only its first `len` bases.
+    pub fn truncated(&self, len: usize) -> SeqRecord {
+        match self {
+            SeqRecord::Fasta(record) => SeqRecord::Fasta(fasta::Record::with_attrs(
+                record.id(),
+                record.desc(),
+                &record.seq()[..len],
+            )),
+            SeqRecord::Fastq(record) => SeqRecord::Fastq(fastq::Record::with_attrs(
+                record.id(),
+                record.desc(),
+                &record.seq()[..len],
+                &record.qual()[..len.min(record.qual().len())],
+            )),
+        }
+    }
+}
+
+/// Where a record was read
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RecordPosition {
+    /// 1-based number of the record in its file
+    pub record: usize,
+    /// Bytes of the decompressed file consumed when the reading of the record started
+    pub byte_offset: u64,
+}
+
+/// Counts the bytes consumed from the inner reader
+struct OffsetReader<R> {
+    inner: R,
+    offset: Rc<Cell<u64>>,
+}
+
+impl<R: BufRead> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.inner.read(buf)?;
+        self.offset.set(self.offset.get() + num_bytes as u64);
+        Ok(num_bytes)
+    }
+}
+
+impl<R: BufRead> BufRead for OffsetReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.offset.set(self.offset.get() + amt as u64);
+    }
+}
+
+/// Feeds the bytes consumed by the parser to a validator
+struct ValidatingReader<R> {
+    inner: R,
+    validator: Rc<RefCell<FastqValidator>>,
+}
+
+impl<R: BufRead> Read for ValidatingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.inner.read(buf)?;
+        let mut validator = self.validator.borrow_mut();
+        match num_bytes {
+            0 => validator.finish(),
+            _ => validator.feed(&buf[..num_bytes]),
+        }
+        Ok(num_bytes)
+    }
+}
+
+impl<R: BufRead> BufRead for ValidatingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let buffer = self.inner.fill_buf()?;
+        if buffer.is_empty() {
+            self.validator.borrow_mut().finish();
+        }
+        Ok(buffer)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The consumed bytes are at the start of the buffer, filled by the caller
+        if let Ok(buffer) = self.inner.fill_buf() {
+            self.validator.borrow_mut().feed(&buffer[..amt]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+enum RecordReader {
+    Fasta(fasta::Reader<Box<dyn BufRead>>),
+    Fastq(fastq::Reader<Box<dyn BufRead>>),
+}
+
+/// FASTA or FASTQ reader, the format is detected when the input is opened
+pub struct SeqReader {
+    reader: RecordReader,
+    path: String,
+    /// Decompressed bytes consumed by the parser
+    offset: Rc<Cell<u64>>,
+    /// Position of the last record read, or being read
+    position: RecordPosition,
+    /// Strict checker of the records, with `--validate`
+    validator: Option<Rc<RefCell<FastqValidator>>>,
+    /// Stop at the first validation issue instead of collecting them
+    stop_at_issue: bool,
+    /// The last record read passed the validation, or there is no validation
+    record_valid: bool,
+}
+
+impl SeqReader {
+    /// Opens a, maybe compressed, FASTA or FASTQ file.
+    /// `"-"` means read from stdin.
+    /// With `validation` every record is checked strictly, only FASTQ files can be validated.
+    /// The reading of the file is counted in `progress`.
+    pub fn open(
+        path: &str,
+        n_threads: usize,
+        validation: Option<&ValidateOptions>,
+        progress: &ReadProgress,
+    ) -> Result<Self, SeqStatsError> {
+        let input = open_maybe_compressed(path, n_threads, progress)?;
+        let offset = Rc::new(Cell::new(0));
+        let mut input: Box<dyn BufRead> = Box::new(OffsetReader {
+            inner: BufReader::new(input),
+            offset: Rc::clone(&offset),
+        });
+        let validator =
+            validation.map(|options| Rc::new(RefCell::new(FastqValidator::new(path, options))));
+        if let Some(validator) = &validator {
+            input = Box::new(ValidatingReader {
+                inner: input,
+                validator: Rc::clone(validator),
+            });
+        }
+        let reader = match detect_format(&mut input) {
+            Ok(SeqFormat::Fasta) => RecordReader::Fasta(fasta::Reader::from_bufread(input)),
+            Ok(SeqFormat::Fastq) => RecordReader::Fastq(fastq::Reader::from_bufread(input)),
+            Err(err) => {
+                let kind = match err.downcast_ref::<io::Error>() {
+                    Some(io_err) => read_error_kind(io_err),
+                    None => error::ErrorKind::Parse,
+                };
+                return Err(SeqStatsError::new(kind, err.to_string()).in_file(path));
+            }
+        };
+        if validator.is_some() && matches!(reader, RecordReader::Fasta(_)) {
+            return Err(SeqStatsError::new(
+                error::ErrorKind::Parse,
+                "only FASTQ input can be validated",
+            )
+            .in_file(path));
+        }
+        let reader = SeqReader {
+            reader,
+            path: path.to_string(),
+            offset,
+            position: RecordPosition::default(),
+            validator,
+            stop_at_issue: validation.is_some_and(|options| !options.collect_all),
+            record_valid: true,
+        };
+        Ok(reader)
+    }
+
+    pub fn format(&self) -> SeqFormat {
+        match self.reader {
+            RecordReader::Fasta(_) => SeqFormat::Fasta,
+            RecordReader::Fastq(_) => SeqFormat::Fastq,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Position of the last record read, or of the end of the input once it is exhausted
+    pub fn position(&self) -> RecordPosition {
+        self.position
+    }
+
+    /// Error located at the last record read
+    pub fn error(&self, kind: error::ErrorKind, msg: impl Into<String>) -> SeqStatsError {
+        SeqStatsError::new(kind, msg)
+            .in_file(&self.path)
+            .at(self.position)
+    }
+
+    /// Whether the last record read passed the validation, always true without validation
+    pub fn record_valid(&self) -> bool {
+        self.record_valid
+    }
+
+    /// Issues found so far by the validation
+    pub fn validation_report(&self) -> Option<ValidationReport> {
+        self.validator
+            .as_ref()
+            .map(|validator| validator.borrow().report().clone())
+    }
+
+    /// Creates an empty record of the format read by this reader.
+    pub fn new_record(&self) -> SeqRecord {
+        match self.reader {
+            RecordReader::Fasta(_) => SeqRecord::Fasta(fasta::Record::new()),
+            RecordReader::Fastq(_) => SeqRecord::Fastq(fastq::Record::new()),
+        }
+    }
+
+    /// Reads the next record into `record`, that must come from `new_record`.
+    /// Returns false at the end of the input.
+    /// With validation, fails at the first issue unless the issues are collected.
+    pub fn read(&mut self, record: &mut SeqRecord) -> Result<bool, SeqStatsError> {
+        self.position = RecordPosition {
+            record: self.position.record + 1,
+            byte_offset: self.offset.get(),
+        };
+        let result = self.parse(record);
+        if let Some(validator) = &self.validator {
+            let (issue, record_valid) = validator.borrow_mut().take_issue();
+            self.record_valid = record_valid;
+            match issue {
+                // The validator issue is more precise than a parser error on the same lines
+                Some(issue) if self.stop_at_issue || result.is_err() => {
+                    return Err(self.error(
+                        error::ErrorKind::Parse,
+                        format!("line {}: {}", issue.line, issue.message),
+                    ))
+                }
+                // Invalid records are left out of the stats, they are not checked further
+                _ if !record_valid => return result,
+                _ => {}
+            }
+        }
+        let read = result?;
+        if read {
+            self.check_qual_len(record)?;
+        }
+        Ok(read)
+    }
+
+    /// The parser does not check that the quality string is as long as the sequence.
+    fn check_qual_len(&self, record: &SeqRecord) -> Result<(), SeqStatsError> {
+        match record.qual() {
+            Some(qual) if qual.len() != record.seq().len() => Err(self.error(
+                error::ErrorKind::Parse,
+                format!(
+                    "quality length {} differs from sequence length {} ({})",
+                    qual.len(),
+                    record.seq().len(),
+                    record.id()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse(&mut self, record: &mut SeqRecord) -> Result<bool, SeqStatsError> {
+        match (&mut self.reader, record) {
+            (RecordReader::Fasta(reader), SeqRecord::Fasta(record)) => {
+                if let Err(err) = reader.read(record) {
+                    return Err(self.error(read_error_kind(&err), err.to_string()));
+                }
+                Ok(!record.is_empty())
+            }
+            (RecordReader::Fastq(reader), SeqRecord::Fastq(record)) => {
+                // Try to read the next record
+                let read_result = reader.read(record);
+
+                // Handle possible errors
+                if let Err(e) = read_result {
+                    return match e {
+                        FastqError::ReadError(ref io_err)
+                            if io_err.kind() == ErrorKind::UnexpectedEof =>
+                        {
+                            Ok(false)
+                        }
+                        FastqError::ReadError(io_err) => {
+                            Err(self.error(read_error_kind(&io_err), io_err.to_string()))
+                        }
+                        FastqError::IncompleteRecord => Err(self.error(
+                            error::ErrorKind::Parse,
+                            "Encountered incomplete FASTQ record",
+                        )),
+                        other => Err(self.error(error::ErrorKind::Parse, other.to_string())),
+                    };
+                }
+
+                // Check for implicit EOF case (blank trailing lines)
+                Ok(!record.seq().is_empty())
+            }
+            _ => unreachable!("record format does not match the reader format"),
+        }
+    }
+}
+
+/// Writes records in the format they were read
+pub enum SeqWriter<W: Write> {
+    Fasta(fasta::Writer<W>),
+    Fastq(fastq::Writer<W>),
+}
+
+impl<W: Write> SeqWriter<W> {
+    pub fn new(format: SeqFormat, writer: W) -> Self {
+        match format {
+            SeqFormat::Fasta => SeqWriter::Fasta(fasta::Writer::new(writer)),
+            SeqFormat::Fastq => SeqWriter::Fastq(fastq::Writer::new(writer)),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &SeqRecord) -> io::Result<()> {
+        match (self, record) {
+            (SeqWriter::Fasta(writer), SeqRecord::Fasta(record)) => writer.write_record(record),
+            (SeqWriter::Fastq(writer), SeqRecord::Fastq(record)) => writer.write_record(record),
+            _ => unreachable!("record format does not match the writer format"),
+        }
+    }
+
+    pub fn write_records(&mut self, records: &[SeqRecord]) -> io::Result<()> {
+        records
+            .iter()
+            .try_for_each(|record| self.write_record(record))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SeqWriter::Fasta(writer) => writer.flush(),
+            SeqWriter::Fastq(writer) => writer.flush(),
+        }
+    }
+}
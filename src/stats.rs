@@ -0,0 +1,510 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::adapters::{self, Adapter, AdapterContent, AdapterHits};
+use crate::duplication::{DuplicationSketch, DuplicationStats};
+use crate::gc_model::{self, ExpectedGc, GcComparison, TheoreticalGc};
+use crate::overrepresented::{OverrepTracker, OverrepresentedSeq};
+
+/// Highest printable quality character allowed by the Sanger and Illumina 1.3+ encodings
+pub const MAX_QUAL_CHAR: u8 = b'~';
+
+/// Quality summary for one read position (cycle), FastQC "per base sequence quality" style
+#[derive(Serialize, Deserialize)]
+pub struct PositionQualStats {
+    /// 1-based read position
+    pub position: usize,
+    /// Number of reads long enough to cover this position
+    pub count: usize,
+    pub mean: f64,
+    pub median: u8,
+    pub p10: u8,
+    pub p25: u8,
+    pub p75: u8,
+    pub p90: u8,
+    /// Reads by Phred score at this position, indexed by the score, so that stats can be merged
+    #[serde(default)]
+    pub phred_counts: Vec<usize>,
+}
+
+/// Base counts for one read position (cycle), case-insensitive
+#[derive(Serialize, Deserialize)]
+pub struct PositionBaseCounts {
+    /// 1-based read position
+    pub position: usize,
+    pub a: usize,
+    pub c: usize,
+    pub g: usize,
+    pub t: usize,
+    pub n: usize,
+    /// IUPAC ambiguity codes other than N (R, Y, S, W, K, M, B, D, H, V)
+    pub ambiguous: usize,
+    /// Any byte that is not a IUPAC nucleotide code
+    pub other: usize,
+}
+
+impl PositionBaseCounts {
+    fn new(position: usize) -> Self {
+        PositionBaseCounts {
+            position,
+            a: 0,
+            c: 0,
+            g: 0,
+            t: 0,
+            n: 0,
+            ambiguous: 0,
+            other: 0,
+        }
+    }
+
+    fn add_base(&mut self, base: u8) {
+        match base {
+            b'A' | b'a' => self.a += 1,
+            b'C' | b'c' => self.c += 1,
+            b'G' | b'g' => self.g += 1,
+            b'T' | b't' => self.t += 1,
+            b'N' | b'n' => self.n += 1,
+            b'R' | b'Y' | b'S' | b'W' | b'K' | b'M' | b'B' | b'D' | b'H' | b'V' | b'r' | b'y'
+            | b's' | b'w' | b'k' | b'm' | b'b' | b'd' | b'h' | b'v' => self.ambiguous += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// Settings that change how reads are counted
+#[derive(Clone)]
+pub struct StatsOptions {
+    pub phred_offset: u8,
+    /// Use only unambiguous bases (A, C, G, T) as the GC percentage denominator
+    pub gc_unambiguous_only: bool,
+    /// Maximum number of distinct sequences sampled for the duplication levels
+    pub dup_max_tracked: usize,
+    /// Minimum fraction of the reads for a sequence to be reported as overrepresented
+    pub overrep_min_fraction: f64,
+    /// Number of bases at the start of the reads used to find overrepresented sequences, 0 for all
+    pub overrep_prefix_len: usize,
+    /// Adapters searched in the reads
+    pub adapters: Vec<Adapter>,
+    /// GC distribution expected for the reads, compared to the observed one
+    pub expected_gc: Option<ExpectedGc>,
+}
+
+/// Per-read base counts gathered while updating the per-position composition
+struct ReadBaseCounts {
+    gc: usize,
+    /// A, C, G and T bases
+    unambiguous: usize,
+    n: usize,
+}
+
+/// Read stats, the histograms are BTreeMaps so that the output is sorted and reproducible
+#[derive(Serialize, Deserialize, Default)]
+pub struct ReadStats {
+    pub total_records: u64,
+    pub gc_distrib: BTreeMap<u8, usize>,
+    /// Normal distribution fitted to `gc_distrib`, filled in by `finish`
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub gc_theoretical: Option<TheoreticalGc>,
+    /// `gc_distrib` compared to the expected distribution of the options, filled in by `finish`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_reference: Option<GcComparison>,
+    #[serde(skip)]
+    expected_gc: Option<ExpectedGc>,
+    pub len_distrib: BTreeMap<usize, usize>,
+    /// Distribution of the per-read mean Phred score (rounded), absent for FASTA input
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub qual_distrib: BTreeMap<u8, usize>,
+    /// Distribution of the per-read N percentage (rounded)
+    pub n_distrib: BTreeMap<u8, usize>,
+    /// Per-position quality summaries, filled in by `finish`, absent for FASTA input
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub qual_by_position: Vec<PositionQualStats>,
+    /// Per-position base counts, including the per-position N count
+    pub base_composition_by_position: Vec<PositionBaseCounts>,
+    /// Sequence duplication levels, filled in by `finish`
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub duplication: Option<DuplicationStats>,
+    #[serde(skip)]
+    duplication_sketch: DuplicationSketch,
+    /// Overrepresented read prefixes, filled in by `finish`
+    pub overrepresented_sequences: Vec<OverrepresentedSeq>,
+    /// Fed by the caller in input order, as its result depends on the read order
+    #[serde(skip)]
+    pub overrep_tracker: OverrepTracker,
+    /// Adapter content by position, filled in by `finish`
+    pub adapter_content: Vec<AdapterContent>,
+    /// Reads per adapter start position, in the order of the adapters in the options
+    #[serde(skip)]
+    adapter_hits: Vec<AdapterHits>,
+    /// Per-position quality histograms: counts indexed by position and then by Phred score
+    #[serde(skip)]
+    qual_counts_by_position: Vec<Vec<usize>>,
+}
+
+impl ReadStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one read to the stats.
+    /// The quality string must be as long as the sequence, the reader checks it.
+    /// Fails if a quality character falls outside the range allowed by the Phred offset.
+    /// Reads without a quality string (FASTA) only update the sequence-based stats.
+    /// With `gc_unambiguous_only`, reads without any A, C, G or T are left out of the GC distribution.
+    pub fn add_read(
+        &mut self,
+        seq: &[u8],
+        qual: Option<&[u8]>,
+        options: &StatsOptions,
+    ) -> Result<(), String> {
+        if let Some(qual) = qual {
+            debug_assert_eq!(qual.len(), seq.len(), "quality and sequence lengths differ");
+        }
+        let base_counts = self.add_bases(seq);
+        let len = seq.len();
+        let gc_denominator = if options.gc_unambiguous_only {
+            base_counts.unambiguous
+        } else {
+            len
+        };
+        if gc_denominator > 0 {
+            let gc_percent =
+                ((base_counts.gc as f64 / gc_denominator as f64) * 100.0).round() as u8;
+            *self.gc_distrib.entry(gc_percent).or_insert(0) += 1;
+        }
+        if len > 0 {
+            let n_percent = ((base_counts.n as f64 / len as f64) * 100.0).round() as u8;
+            *self.n_distrib.entry(n_percent).or_insert(0) += 1;
+        }
+        *self.len_distrib.entry(len).or_insert(0) += 1;
+
+        if let Some(qual) = qual {
+            let mean_qual = self.add_qual(qual, options.phred_offset)?;
+            *self
+                .qual_distrib
+                .entry(mean_qual.round() as u8)
+                .or_insert(0) += 1;
+        }
+
+        self.duplication_sketch.max_tracked = options.dup_max_tracked;
+        self.duplication_sketch.add(seq);
+
+        self.add_adapter_hits(seq, &options.adapters);
+        if self.expected_gc.is_none() {
+            self.expected_gc.clone_from(&options.expected_gc);
+        }
+
+        self.total_records += 1;
+        Ok(())
+    }
+
+    /// Updates the per-position base counts and returns the base counts of the read.
+    fn add_bases(&mut self, seq: &[u8]) -> ReadBaseCounts {
+        while self.base_composition_by_position.len() < seq.len() {
+            let position = self.base_composition_by_position.len() + 1;
+            self.base_composition_by_position
+                .push(PositionBaseCounts::new(position));
+        }
+        let mut counts = ReadBaseCounts {
+            gc: 0,
+            unambiguous: 0,
+            n: 0,
+        };
+        for (&base, pos_counts) in seq.iter().zip(self.base_composition_by_position.iter_mut()) {
+            pos_counts.add_base(base);
+            match base {
+                b'G' | b'g' | b'C' | b'c' => {
+                    counts.gc += 1;
+                    counts.unambiguous += 1;
+                }
+                b'A' | b'a' | b'T' | b't' => counts.unambiguous += 1,
+                b'N' | b'n' => counts.n += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    /// Records where each adapter is first found in the read.
+    fn add_adapter_hits(&mut self, seq: &[u8], adapters: &[Adapter]) {
+        if self.adapter_hits.is_empty() {
+            self.adapter_hits = adapters.iter().map(AdapterHits::new).collect();
+        }
+        let seq = adapters::to_upper(seq);
+        for (adapter, hits) in adapters.iter().zip(self.adapter_hits.iter_mut()) {
+            if let Some(position) = adapter.find(&seq) {
+                hits.add_hit(position);
+            }
+        }
+    }
+
+    /// Updates the per-position quality histograms and returns the mean Phred score of the read.
+    fn add_qual(&mut self, qual: &[u8], phred_offset: u8) -> Result<f64, String> {
+        if self.qual_counts_by_position.len() < qual.len() {
+            self.qual_counts_by_position
+                .resize_with(qual.len(), Vec::new);
+        }
+        let mut qual_sum: usize = 0;
+        for (&qual_char, pos_counts) in qual.iter().zip(self.qual_counts_by_position.iter_mut()) {
+            if qual_char < phred_offset || qual_char > MAX_QUAL_CHAR {
+                return Err(format!(
+                    "Quality character '{}' (byte {}) out of range for Phred+{}",
+                    qual_char as char, qual_char, phred_offset
+                ));
+            }
+            let phred = (qual_char - phred_offset) as usize;
+            if pos_counts.len() <= phred {
+                pos_counts.resize(phred + 1, 0);
+            }
+            pos_counts[phred] += 1;
+            qual_sum += phred;
+        }
+        Ok(qual_sum as f64 / qual.len() as f64)
+    }
+
+    /// Adds the counts of another, not yet finished, `ReadStats` to this one.
+    pub fn merge(&mut self, other: &ReadStats) {
+        self.total_records += other.total_records;
+        merge_counts(&mut self.gc_distrib, &other.gc_distrib);
+        merge_counts(&mut self.len_distrib, &other.len_distrib);
+        merge_counts(&mut self.qual_distrib, &other.qual_distrib);
+        merge_counts(&mut self.n_distrib, &other.n_distrib);
+        self.duplication_sketch.merge(&other.duplication_sketch);
+        self.overrep_tracker.merge(&other.overrep_tracker);
+        if self.expected_gc.is_none() {
+            self.expected_gc.clone_from(&other.expected_gc);
+        }
+        if self.adapter_hits.is_empty() {
+            self.adapter_hits.clone_from(&other.adapter_hits);
+        } else {
+            for (hits, other_hits) in self.adapter_hits.iter_mut().zip(&other.adapter_hits) {
+                hits.merge(other_hits);
+            }
+        }
+
+        for (idx, other_counts) in other.base_composition_by_position.iter().enumerate() {
+            if idx == self.base_composition_by_position.len() {
+                self.base_composition_by_position
+                    .push(PositionBaseCounts::new(idx + 1));
+            }
+            let counts = &mut self.base_composition_by_position[idx];
+            counts.a += other_counts.a;
+            counts.c += other_counts.c;
+            counts.g += other_counts.g;
+            counts.t += other_counts.t;
+            counts.n += other_counts.n;
+            counts.ambiguous += other_counts.ambiguous;
+            counts.other += other_counts.other;
+        }
+
+        if self.qual_counts_by_position.len() < other.qual_counts_by_position.len() {
+            self.qual_counts_by_position
+                .resize_with(other.qual_counts_by_position.len(), Vec::new);
+        }
+        for (counts, other_counts) in self
+            .qual_counts_by_position
+            .iter_mut()
+            .zip(&other.qual_counts_by_position)
+        {
+            if counts.len() < other_counts.len() {
+                counts.resize(other_counts.len(), 0);
+            }
+            for (count, other_count) in counts.iter_mut().zip(other_counts) {
+                *count += other_count;
+            }
+        }
+    }
+
+    /// Computes the summaries derived from the streaming histograms.
+    pub fn finish(&mut self) {
+        self.gc_theoretical = gc_model::theoretical_gc(&self.gc_distrib, &self.len_distrib);
+        self.gc_reference = self
+            .expected_gc
+            .as_ref()
+            .map(|expected_gc| gc_model::compare_gc(&self.gc_distrib, expected_gc));
+        self.qual_by_position = self
+            .qual_counts_by_position
+            .iter()
+            .enumerate()
+            .map(|(idx, counts)| summarize_position_quals(idx + 1, counts))
+            .collect();
+        self.duplication = Some(self.duplication_sketch.summarize());
+        self.overrepresented_sequences =
+            self.overrep_tracker.summarize(self.total_records as usize);
+        let max_len = self.base_composition_by_position.len();
+        self.adapter_content = self
+            .adapter_hits
+            .iter()
+            .map(|hits| hits.summarize(self.total_records as usize, max_len))
+            .collect();
+    }
+
+    /// Rebuilds the histograms of finished stats read from a stats file from their summaries,
+    /// so that they can be merged and finished again.
+    /// The duplication sketch can not be rebuilt, and the overrepresented sequences tracker
+    /// only gets the sequences listed in the file, to be reported above `overrep_min_fraction`.
+    pub fn restore_accumulators(&mut self, overrep_min_fraction: f64) -> Result<(), String> {
+        if self
+            .qual_by_position
+            .iter()
+            .any(|position| position.count > 0 && position.phred_counts.is_empty())
+        {
+            return Err(
+                "qual_by_position has no phred_counts, the file was written by an older version"
+                    .to_string(),
+            );
+        }
+        self.qual_counts_by_position = self
+            .qual_by_position
+            .iter()
+            .map(|position| position.phred_counts.clone())
+            .collect();
+        let total_reads = self.total_records as usize;
+        self.adapter_hits = self
+            .adapter_content
+            .iter()
+            .map(|content| AdapterHits::from_content(content, total_reads))
+            .collect();
+        self.overrep_tracker =
+            OverrepTracker::from_summary(&self.overrepresented_sequences, overrep_min_fraction);
+        self.expected_gc = self.gc_reference.as_ref().and_then(|reference| {
+            let total: f64 = reference.expected_distrib.values().sum();
+            (total > 0.0).then(|| {
+                reference
+                    .expected_distrib
+                    .iter()
+                    .map(|(&gc, &reads)| (gc, reads / total))
+                    .collect()
+            })
+        });
+        Ok(())
+    }
+}
+
+/// Stats for paired-end input: one set per mate plus both mates together
+#[derive(Serialize, Deserialize)]
+pub struct PairedReadStats {
+    pub total_pairs: u64,
+    pub r1: ReadStats,
+    pub r2: ReadStats,
+    pub combined: ReadStats,
+}
+
+impl PairedReadStats {
+    pub fn new(r1: ReadStats, r2: ReadStats) -> Self {
+        let mut combined = ReadStats::new();
+        combined.merge(&r1);
+        combined.merge(&r2);
+        PairedReadStats {
+            total_pairs: r1.total_records,
+            r1,
+            r2,
+            combined,
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.r1.finish();
+        self.r2.finish();
+        self.combined.finish();
+    }
+}
+
+/// Stats of single reads, or of read pairs
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum FragmentStats {
+    Single(Box<ReadStats>),
+    Paired(Box<PairedReadStats>),
+}
+
+impl FragmentStats {
+    /// Finishes the stats of each mate, one mate for single reads and two for pairs.
+    pub fn from_mates(mate_stats: Vec<ReadStats>) -> Self {
+        let mut mate_stats = mate_stats.into_iter();
+        let (Some(first_stats), second_stats) = (mate_stats.next(), mate_stats.next()) else {
+            unreachable!("fragments have at least one mate");
+        };
+        match second_stats {
+            None => {
+                let mut stats = first_stats;
+                stats.finish();
+                FragmentStats::Single(Box::new(stats))
+            }
+            Some(second_stats) => {
+                let mut stats = PairedReadStats::new(first_stats, second_stats);
+                stats.finish();
+                FragmentStats::Paired(Box::new(stats))
+            }
+        }
+    }
+}
+
+fn merge_counts<K: Ord + Copy>(counts: &mut BTreeMap<K, usize>, other: &BTreeMap<K, usize>) {
+    for (key, count) in other {
+        *counts.entry(*key).or_insert(0) += count;
+    }
+}
+
+fn summarize_position_quals(position: usize, counts: &[usize]) -> PositionQualStats {
+    let count: usize = counts.iter().sum();
+    let qual_sum: usize = counts.iter().enumerate().map(|(phred, n)| phred * n).sum();
+    PositionQualStats {
+        position,
+        count,
+        mean: qual_sum as f64 / count as f64,
+        median: histogram_percentile(counts, count, 50.0),
+        p10: histogram_percentile(counts, count, 10.0),
+        p25: histogram_percentile(counts, count, 25.0),
+        p75: histogram_percentile(counts, count, 75.0),
+        p90: histogram_percentile(counts, count, 90.0),
+        phred_counts: counts.to_vec(),
+    }
+}
+
+/// Nearest-rank percentile of a histogram whose bins are indexed by value.
+fn histogram_percentile(counts: &[usize], total: usize, percentile: f64) -> u8 {
+    let rank = ((percentile / 100.0 * total as f64).ceil() as usize).max(1);
+    let mut cumulative = 0;
+    for (value, n) in counts.iter().enumerate() {
+        cumulative += n;
+        if cumulative >= rank {
+            return value as u8;
+        }
+    }
+    (counts.len() - 1) as u8
+}
+
+/// Stats options and reads shared by the tests of the modules that compute stats
+#[cfg(test)]
+pub mod test_fixtures {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Default options of the command line, with the builtin adapters
+    pub fn options() -> StatsOptions {
+        StatsOptions {
+            phred_offset: 33,
+            gc_unambiguous_only: false,
+            dup_max_tracked: 100_000,
+            overrep_min_fraction: 0.001,
+            overrep_prefix_len: 50,
+            adapters: adapters::builtin_adapters(),
+            expected_gc: None,
+        }
+    }
+
+    /// Reads of 20 to 119 random bases, N included, with random qualities. Always the same ones.
+    pub fn random_reads(num_reads: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..num_reads)
+            .map(|_| {
+                let len = rng.gen_range(20..120);
+                let seq = (0..len).map(|_| b"ACGTN"[rng.gen_range(0..5)]).collect();
+                let qual = (0..len).map(|_| rng.gen_range(b'!'..=b'J')).collect();
+                (seq, qual)
+            })
+            .collect()
+    }
+}
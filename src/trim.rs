@@ -0,0 +1,225 @@
+use serde::Serialize;
+
+use crate::adapters::{self, Adapter};
+use crate::seq_io::SeqRecord;
+
+pub struct TrimOptions {
+    /// Adapters removed from the 3' end, empty to skip adapter trimming
+    pub adapters: Vec<Adapter>,
+    /// Minimum overlap between the read end and the adapter start to trim a partial adapter
+    pub min_adapter_overlap: usize,
+    /// Sliding window quality trimming: window size and minimum mean Phred score
+    pub quality_window: Option<(usize, u8)>,
+    /// Minimum length of the 3' poly-G tails to trim
+    pub poly_g_min_len: Option<usize>,
+    /// Minimum length of the 3' poly-A tails to trim
+    pub poly_a_min_len: Option<usize>,
+    /// Fragments with a read shorter than this after trimming are dropped
+    pub min_len: usize,
+    pub phred_offset: u8,
+}
+
+#[derive(Serialize, Default)]
+pub struct TrimStats {
+    pub reads_adapter_trimmed: usize,
+    pub reads_poly_g_trimmed: usize,
+    pub reads_poly_a_trimmed: usize,
+    pub reads_quality_trimmed: usize,
+    pub bases_trimmed: usize,
+    /// Fragments dropped because a read ended up shorter than the minimum length
+    pub fragments_too_short: usize,
+}
+
+/// Trims reads: 3' adapters first, then poly-G and poly-A tails and finally low quality 3' ends
+pub struct Trimmer {
+    options: TrimOptions,
+}
+
+impl Trimmer {
+    pub fn new(options: TrimOptions) -> Self {
+        Trimmer { options }
+    }
+
+    /// Trims every read of the fragment.
+    /// Returns None if any read ends up shorter than the minimum length.
+    pub fn trim_fragment(
+        &self,
+        fragment: &[SeqRecord],
+        stats: &mut TrimStats,
+    ) -> Option<Vec<SeqRecord>> {
+        let trimmed_lens: Vec<usize> = fragment
+            .iter()
+            .map(|record| self.trimmed_len(record, stats))
+            .collect();
+        if trimmed_lens.iter().any(|&len| len < self.options.min_len) {
+            stats.fragments_too_short += 1;
+            return None;
+        }
+        Some(
+            fragment
+                .iter()
+                .zip(trimmed_lens)
+                .map(|(record, len)| record.truncated(len))
+                .collect(),
+        )
+    }
+
+    /// Length of the read left after trimming.
+    fn trimmed_len(&self, record: &SeqRecord, stats: &mut TrimStats) -> usize {
+        let seq = adapters::to_upper(record.seq());
+        let mut len = seq.len();
+
+        if let Some(adapter_start) = self.find_adapter(&seq) {
+            len = adapter_start;
+            stats.reads_adapter_trimmed += 1;
+        }
+        if let Some(min_len) = self.options.poly_g_min_len {
+            let tail_len = poly_tail_len(&seq[..len], b'G');
+            if tail_len >= min_len {
+                len -= tail_len;
+                stats.reads_poly_g_trimmed += 1;
+            }
+        }
+        if let Some(min_len) = self.options.poly_a_min_len {
+            let tail_len = poly_tail_len(&seq[..len], b'A');
+            if tail_len >= min_len {
+                len -= tail_len;
+                stats.reads_poly_a_trimmed += 1;
+            }
+        }
+        if let (Some((window_size, min_qual)), Some(qual)) =
+            (self.options.quality_window, record.qual())
+        {
+            let quality_len = sliding_window_len(
+                &qual[..len.min(qual.len())],
+                window_size,
+                min_qual,
+                self.options.phred_offset,
+            );
+            if quality_len < len {
+                len = quality_len;
+                stats.reads_quality_trimmed += 1;
+            }
+        }
+
+        stats.bases_trimmed += seq.len() - len;
+        len
+    }
+
+    /// Leftmost start of any adapter, either whole within the read or partially overlapping its 3' end.
+    fn find_adapter(&self, seq: &[u8]) -> Option<usize> {
+        self.options
+            .adapters
+            .iter()
+            .filter_map(|adapter| {
+                adapter
+                    .find(seq)
+                    .or_else(|| self.partial_adapter_start(seq, &adapter.seq))
+            })
+            .min()
+    }
+
+    /// Start of the longest read suffix that matches the start of the adapter.
+    fn partial_adapter_start(&self, seq: &[u8], adapter_seq: &[u8]) -> Option<usize> {
+        let max_overlap = adapter_seq.len().saturating_sub(1).min(seq.len());
+        (self.options.min_adapter_overlap..=max_overlap)
+            .rev()
+            .find(|&overlap| seq[seq.len() - overlap..] == adapter_seq[..overlap])
+            .map(|overlap| seq.len() - overlap)
+    }
+}
+
+/// Number of consecutive `base` at the end of an upper case read.
+fn poly_tail_len(seq: &[u8], base: u8) -> usize {
+    seq.iter().rev().take_while(|&&b| b == base).count()
+}
+
+/// Length kept by sliding window trimming: the read is cut at the start of the first window
+/// whose mean quality falls below `min_qual`.
+fn sliding_window_len(qual: &[u8], window_size: usize, min_qual: u8, phred_offset: u8) -> usize {
+    if window_size == 0 || qual.len() < window_size {
+        return qual.len();
+    }
+    let phred = |qual_char: u8| qual_char.saturating_sub(phred_offset) as usize;
+    let min_sum = min_qual as usize * window_size;
+    let mut window_sum: usize = qual[..window_size].iter().map(|&q| phred(q)).sum();
+    for start in 0..=qual.len() - window_size {
+        if start > 0 {
+            window_sum = window_sum + phred(qual[start + window_size - 1]) - phred(qual[start - 1]);
+        }
+        if window_sum < min_sum {
+            return start;
+        }
+    }
+    qual.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trimmer_with_overlap(min_adapter_overlap: usize) -> Trimmer {
+        Trimmer::new(TrimOptions {
+            adapters: Vec::new(),
+            min_adapter_overlap,
+            quality_window: None,
+            poly_g_min_len: None,
+            poly_a_min_len: None,
+            min_len: 1,
+            phred_offset: 33,
+        })
+    }
+
+    #[test]
+    fn partial_adapter_is_the_longest_overlap() {
+        let adapter = b"AGATCGGAAG";
+        let trimmer = trimmer_with_overlap(3);
+        assert_eq!(
+            trimmer.partial_adapter_start(b"ACGTACGTAGATC", adapter),
+            Some(8)
+        );
+        assert_eq!(
+            trimmer.partial_adapter_start(b"ACGTACGTAGA", adapter),
+            Some(8)
+        );
+        // Shorter overlaps are left
+        assert_eq!(trimmer.partial_adapter_start(b"ACGTACGTAG", adapter), None);
+        assert_eq!(
+            trimmer_with_overlap(1).partial_adapter_start(b"ACGTACGTAG", adapter),
+            Some(8)
+        );
+        // The whole adapter is found by the adapter search, not as a partial one
+        assert_eq!(
+            trimmer.partial_adapter_start(b"CCAGATCGGAAG", adapter),
+            None
+        );
+        // A read shorter than the adapter can be all adapter
+        assert_eq!(trimmer.partial_adapter_start(b"AGAT", adapter), Some(0));
+        assert_eq!(trimmer.partial_adapter_start(b"AG", adapter), None);
+        assert_eq!(trimmer.partial_adapter_start(b"", adapter), None);
+    }
+
+    #[test]
+    fn poly_tail_is_counted_from_the_end() {
+        assert_eq!(poly_tail_len(b"ACGGGG", b'G'), 4);
+        assert_eq!(poly_tail_len(b"GGGG", b'G'), 4);
+        assert_eq!(poly_tail_len(b"GGGA", b'G'), 0);
+        assert_eq!(poly_tail_len(b"", b'G'), 0);
+    }
+
+    #[test]
+    fn read_is_cut_at_the_first_low_quality_window() {
+        // Phred 40 and 2 with the +33 offset
+        assert_eq!(sliding_window_len(b"IIIIIIII", 4, 20, 33), 8);
+        // Windows starting at 0 to 2 average at least 20, the one at 3 does not
+        assert_eq!(sliding_window_len(b"IIII####", 4, 20, 33), 3);
+        assert_eq!(sliding_window_len(b"####IIII", 4, 20, 33), 0);
+        // The last window ends at the read end
+        assert_eq!(sliding_window_len(b"IIIIIII#", 4, 30, 33), 8);
+        assert_eq!(sliding_window_len(b"IIIIII##", 4, 30, 33), 4);
+        // Reads shorter than the window are kept
+        assert_eq!(sliding_window_len(b"##", 4, 20, 33), 2);
+        assert_eq!(sliding_window_len(b"", 4, 20, 33), 0);
+        assert_eq!(sliding_window_len(b"####", 1, 20, 33), 0);
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::stats::MAX_QUAL_CHAR;
+
+/// Maximum number of issues listed in the report, all of them are counted
+const MAX_REPORTED_ISSUES: usize = 1000;
+
+/// Number of bit positions set per read ID in the Bloom filter
+const BLOOM_NUM_HASHES: u64 = 4;
+
+/// Bases accepted in the sequences: the IUPAC nucleotide codes
+const VALID_BASES: &[u8] = b"ACGTUNRYSWKMBDHVacgtunryswkmbdhv";
+
+/// How to look for duplicate read IDs
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum DupIdCheck {
+    /// Keep every read ID in memory
+    Exact,
+    /// Use a fixed size Bloom filter, a few IDs may be wrongly reported as duplicates
+    Bloom,
+    /// Do not look for duplicate read IDs
+    Off,
+}
+
+#[derive(Clone)]
+pub struct ValidateOptions {
+    /// Keep reading after an issue, leaving the fragments with invalid records out of the stats
+    pub collect_all: bool,
+    pub dup_id_check: DupIdCheck,
+    /// Size of the Bloom filter used with `DupIdCheck::Bloom`
+    pub bloom_bytes: usize,
+    pub phred_offset: u8,
+    /// The file holds both mates of each pair, so every read ID is expected twice
+    pub interleaved: bool,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    MissingHeaderAt,
+    MissingSeparator,
+    SeparatorMismatch,
+    MultiLineRecord,
+    LengthMismatch,
+    IllegalSeqChar,
+    IllegalQualChar,
+    DuplicateId,
+    WindowsLineEnding,
+    TruncatedRecord,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ValidationIssue {
+    pub file: String,
+    /// 1-based line number in the decompressed file
+    pub line: u64,
+    /// 1-based number of the record holding the line
+    pub record: u64,
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub num_issues: u64,
+    /// Records with at least one issue, left out of the stats with their mates
+    pub invalid_records: u64,
+    pub issues_by_kind: BTreeMap<IssueKind, u64>,
+    /// First issues found, up to 1000
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn merge(&mut self, other: &ValidationReport) {
+        self.valid = self.valid && other.valid;
+        self.num_issues += other.num_issues;
+        self.invalid_records += other.invalid_records;
+        for (kind, count) in &other.issues_by_kind {
+            *self.issues_by_kind.entry(*kind).or_insert(0) += count;
+        }
+        let num_free = MAX_REPORTED_ISSUES.saturating_sub(self.issues.len());
+        self.issues
+            .extend(other.issues.iter().take(num_free).cloned());
+    }
+}
+
+enum SeenIds {
+    Exact(HashSet<Vec<u8>>),
+    Bloom(Vec<u64>),
+    Off,
+}
+
+impl SeenIds {
+    fn new(check: DupIdCheck, bloom_bytes: usize) -> Self {
+        match check {
+            DupIdCheck::Exact => SeenIds::Exact(HashSet::new()),
+            DupIdCheck::Bloom => SeenIds::Bloom(vec![0; bloom_bytes.div_ceil(8).max(1)]),
+            DupIdCheck::Off => SeenIds::Off,
+        }
+    }
+
+    /// Adds an ID, returns true if it was seen before, or probably seen with the Bloom filter.
+    fn insert(&mut self, id: &[u8]) -> bool {
+        match self {
+            SeenIds::Exact(ids) => !ids.insert(id.to_vec()),
+            SeenIds::Bloom(words) => {
+                let mut hasher = DefaultHasher::new();
+                id.hash(&mut hasher);
+                let hash = hasher.finish();
+                // Double hashing: the bit positions are h1 + i * h2
+                let step = hash.rotate_left(32) | 1;
+                let num_bits = words.len() as u64 * 64;
+                let mut seen = true;
+                for i in 0..BLOOM_NUM_HASHES {
+                    let bit = hash.wrapping_add(i.wrapping_mul(step)) % num_bits;
+                    let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+                    seen &= words[word] & mask != 0;
+                    words[word] |= mask;
+                }
+                seen
+            }
+            SeenIds::Off => false,
+        }
+    }
+}
+
+/// Line of a record expected next
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RecordPart {
+    Header,
+    /// Sequence lines read so far, they end at the `+` separator
+    Seq(usize),
+    /// Quality lines still to read
+    Qual(usize),
+}
+
+/// Strict line based FASTQ checker, fed with the bytes of a file as they are parsed.
+/// Records must take exactly four lines: the `@` header, the sequence,
+/// the `+` separator, optionally repeating the header, and the quality.
+/// The records are split into lines like the parser does, the sequence lines go up
+/// to the separator and are followed by as many quality lines, so an issue in a record
+/// does not shift the lines of the following ones.
+pub struct FastqValidator {
+    path: String,
+    options: ValidateOptions,
+    seen_ids: SeenIds,
+    /// Bytes of the line being read, up to the next newline
+    line: Vec<u8>,
+    num_lines: u64,
+    /// Records started, the current one included
+    num_records: u64,
+    part: RecordPart,
+    /// Header of the current record, without the `@`
+    header: Vec<u8>,
+    seq_len: usize,
+    qual_len: usize,
+    crlf_found: bool,
+    finished: bool,
+    /// Issues in the current record, marks it as invalid
+    record_has_issue: bool,
+    /// First issue not yet taken by `take_issue`
+    pending_issue: Option<ValidationIssue>,
+    report: ValidationReport,
+}
+
+impl FastqValidator {
+    pub fn new(path: &str, options: &ValidateOptions) -> Self {
+        FastqValidator {
+            path: path.to_string(),
+            options: options.clone(),
+            seen_ids: SeenIds::new(options.dup_id_check, options.bloom_bytes),
+            line: Vec::new(),
+            num_lines: 0,
+            num_records: 0,
+            part: RecordPart::Header,
+            header: Vec::new(),
+            seq_len: 0,
+            qual_len: 0,
+            crlf_found: false,
+            finished: false,
+            record_has_issue: false,
+            pending_issue: None,
+            report: ValidationReport {
+                valid: true,
+                ..ValidationReport::default()
+            },
+        }
+    }
+
+    /// Checks the lines completed by `bytes`, the next bytes of the file.
+    pub fn feed(&mut self, mut bytes: &[u8]) {
+        while let Some(newline_pos) = memchr::memchr(b'\n', bytes) {
+            if self.line.is_empty() {
+                self.check_line(&bytes[..newline_pos]);
+            } else {
+                let mut line = std::mem::take(&mut self.line);
+                line.extend_from_slice(&bytes[..newline_pos]);
+                self.check_line(&line);
+                line.clear();
+                self.line = line;
+            }
+            bytes = &bytes[newline_pos + 1..];
+        }
+        self.line.extend_from_slice(bytes);
+    }
+
+    /// Checks the last line, that may lack the newline, and that the last record is complete.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.check_line(&line);
+        }
+        let missing = match self.part {
+            RecordPart::Header => return,
+            RecordPart::Seq(_) => "its separator and quality",
+            RecordPart::Qual(_) => "part of its quality",
+        };
+        self.add_issue(
+            IssueKind::TruncatedRecord,
+            format!("file ends in the middle of a record, without {}", missing),
+        );
+    }
+
+    /// Returns the first issue found since the last call, and whether the record
+    /// read since the last call is valid.
+    pub fn take_issue(&mut self) -> (Option<ValidationIssue>, bool) {
+        let record_valid = !self.record_has_issue;
+        if self.record_has_issue {
+            self.report.invalid_records += 1;
+            self.record_has_issue = false;
+        }
+        (self.pending_issue.take(), record_valid)
+    }
+
+    pub fn report(&self) -> &ValidationReport {
+        &self.report
+    }
+
+    fn check_line(&mut self, line: &[u8]) {
+        self.num_lines += 1;
+        let line = match line.strip_suffix(b"\r") {
+            Some(line) => {
+                if !self.crlf_found {
+                    self.crlf_found = true;
+                    self.add_issue(
+                        IssueKind::WindowsLineEnding,
+                        "Windows (CRLF) line ending, the file may have more".to_string(),
+                    );
+                }
+                line
+            }
+            None => line,
+        };
+
+        match self.part {
+            RecordPart::Header => {
+                self.num_records += 1;
+                self.check_header(line);
+                self.part = RecordPart::Seq(0);
+            }
+            RecordPart::Seq(num_seq_lines) if line.starts_with(b"+") => {
+                self.check_separator(line, num_seq_lines);
+                // As the parser does, a record without sequence lines still takes a quality line
+                self.part = RecordPart::Qual(num_seq_lines.max(1));
+            }
+            RecordPart::Seq(num_seq_lines) => {
+                self.check_seq(line, num_seq_lines);
+                self.part = RecordPart::Seq(num_seq_lines + 1);
+            }
+            RecordPart::Qual(num_qual_lines) => {
+                self.check_qual(line);
+                if num_qual_lines > 1 {
+                    self.part = RecordPart::Qual(num_qual_lines - 1);
+                } else {
+                    self.check_qual_len();
+                    self.part = RecordPart::Header;
+                }
+            }
+        }
+    }
+
+    fn check_header(&mut self, line: &[u8]) {
+        self.header.clear();
+        self.seq_len = 0;
+        self.qual_len = 0;
+        let Some(header) = line.strip_prefix(b"@") else {
+            self.add_record_issue(
+                IssueKind::MissingHeaderAt,
+                format!("header line does not start with '@': '{}'", excerpt(line)),
+            );
+            return;
+        };
+        self.header.extend_from_slice(header);
+
+        let id_len = header
+            .iter()
+            .position(|byte| byte.is_ascii_whitespace())
+            .unwrap_or(header.len());
+        let mut key = header[..id_len].to_vec();
+        if self.options.interleaved {
+            // Both mates of a pair may share the ID
+            key.push((self.num_records % 2) as u8);
+        }
+        if self.seen_ids.insert(&key) {
+            let message = match self.seen_ids {
+                SeenIds::Bloom(_) => format!(
+                    "read ID '{}' was probably seen before (Bloom filter check)",
+                    excerpt(&header[..id_len])
+                ),
+                _ => format!("read ID '{}' was seen before", excerpt(&header[..id_len])),
+            };
+            self.add_record_issue(IssueKind::DuplicateId, message);
+        }
+    }
+
+    fn check_seq(&mut self, line: &[u8], num_seq_lines: usize) {
+        self.seq_len += line.len();
+        if num_seq_lines > 0 && line.starts_with(b"@") {
+            // The parser takes the line as sequence, it is probably the next record
+            self.add_record_issue(
+                IssueKind::MissingSeparator,
+                format!(
+                    "no '+' separator line before the header-like line '{}'",
+                    excerpt(line)
+                ),
+            );
+        } else if let Some(&base) = line.iter().find(|base| !VALID_BASES.contains(base)) {
+            self.add_record_issue(
+                IssueKind::IllegalSeqChar,
+                format!("illegal sequence character {}", describe_byte(base)),
+            );
+        }
+    }
+
+    fn check_separator(&mut self, line: &[u8], num_seq_lines: usize) {
+        if num_seq_lines != 1 {
+            self.add_record_issue(
+                IssueKind::MultiLineRecord,
+                format!(
+                    "sequence takes {} lines instead of one, the record must take four lines",
+                    num_seq_lines
+                ),
+            );
+        }
+        let title = &line[1..];
+        if !title.is_empty() && title != self.header {
+            self.add_record_issue(
+                IssueKind::SeparatorMismatch,
+                "separator line does not repeat the header".to_string(),
+            );
+        }
+    }
+
+    fn check_qual(&mut self, line: &[u8]) {
+        self.qual_len += line.len();
+        let phred_offset = self.options.phred_offset;
+        if let Some(&qual_char) = line
+            .iter()
+            .find(|&&qual_char| qual_char < phred_offset || qual_char > MAX_QUAL_CHAR)
+        {
+            self.add_record_issue(
+                IssueKind::IllegalQualChar,
+                format!(
+                    "quality character {} out of range for Phred+{}",
+                    describe_byte(qual_char),
+                    phred_offset
+                ),
+            );
+        }
+    }
+
+    /// Checks the quality length once the last quality line of the record is read.
+    fn check_qual_len(&mut self) {
+        if self.qual_len != self.seq_len {
+            self.add_record_issue(
+                IssueKind::LengthMismatch,
+                format!(
+                    "quality length {} differs from sequence length {}",
+                    self.qual_len, self.seq_len
+                ),
+            );
+        }
+    }
+
+    /// Adds an issue that makes the current record invalid.
+    fn add_record_issue(&mut self, kind: IssueKind, message: String) {
+        self.record_has_issue = true;
+        self.add_issue(kind, message);
+    }
+
+    fn add_issue(&mut self, kind: IssueKind, message: String) {
+        let issue = ValidationIssue {
+            file: self.path.clone(),
+            line: self.num_lines,
+            record: self.num_records.max(1),
+            kind,
+            message,
+        };
+        let report = &mut self.report;
+        report.valid = false;
+        report.num_issues += 1;
+        *report.issues_by_kind.entry(kind).or_insert(0) += 1;
+        if report.issues.len() < MAX_REPORTED_ISSUES {
+            report.issues.push(issue.clone());
+        }
+        self.pending_issue.get_or_insert(issue);
+    }
+}
+
+/// Start of a line, to quote it in messages.
+fn excerpt(line: &[u8]) -> String {
+    const MAX_LEN: usize = 40;
+    let text = String::from_utf8_lossy(&line[..line.len().min(MAX_LEN)]);
+    if line.len() > MAX_LEN {
+        format!("{}...", text)
+    } else {
+        text.into_owned()
+    }
+}
+
+fn describe_byte(byte: u8) -> String {
+    if byte.is_ascii_graphic() {
+        format!("'{}' (byte {})", byte as char, byte)
+    } else {
+        format!("byte {}", byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(fastq: &[u8]) -> ValidationReport {
+        let options = ValidateOptions {
+            collect_all: true,
+            dup_id_check: DupIdCheck::Exact,
+            bloom_bytes: 0,
+            phred_offset: 33,
+            interleaved: false,
+        };
+        let mut validator = FastqValidator::new("test.fq", &options);
+        // Lines split across feeds are joined
+        for chunk in fastq.chunks(7) {
+            validator.feed(chunk);
+        }
+        validator.finish();
+        validator.report().clone()
+    }
+
+    fn record(id: usize, seq_lines: &[&str]) -> String {
+        let qual_lines: Vec<String> = seq_lines
+            .iter()
+            .map(|line| "I".repeat(line.len()))
+            .collect();
+        format!(
+            "@r{}\n{}\n+\n{}\n",
+            id,
+            seq_lines.join("\n"),
+            qual_lines.join("\n")
+        )
+    }
+
+    #[test]
+    fn valid_records_have_no_issues() {
+        let fastq: String = (0..10).map(|id| record(id, &["ACGTACGT"])).collect();
+        let report = validate(fastq.as_bytes());
+        assert!(report.valid);
+        assert_eq!(report.num_issues, 0);
+    }
+
+    #[test]
+    fn multi_line_record_does_not_shift_the_next_ones() {
+        let fastq: String = (0..10)
+            .map(|id| match id {
+                3 => record(id, &["ACGTACGT", "ACGT"]),
+                _ => record(id, &["ACGTACGT"]),
+            })
+            .collect();
+        let report = validate(fastq.as_bytes());
+        assert_eq!(report.num_issues, 1);
+        let issue = &report.issues[0];
+        assert_eq!(issue.kind, IssueKind::MultiLineRecord);
+        assert_eq!((issue.line, issue.record), (16, 4));
+    }
+
+    #[test]
+    fn missing_separator_is_found() {
+        let fastq = format!("@r0\nACGT\n{}", record(1, &["ACGT"]));
+        let report = validate(fastq.as_bytes());
+        assert_eq!(report.issues[0].kind, IssueKind::MissingSeparator);
+        assert_eq!(report.issues[0].line, 3);
+    }
+
+    #[test]
+    fn record_issues_are_found() {
+        let fastq = "@r0\nACXT\n+r1\nII\n@r0\nACGT\n+\nII I\n";
+        let report = validate(fastq.as_bytes());
+        let kinds: Vec<IssueKind> = report.issues.iter().map(|issue| issue.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                IssueKind::IllegalSeqChar,
+                IssueKind::SeparatorMismatch,
+                IssueKind::LengthMismatch,
+                IssueKind::DuplicateId,
+                IssueKind::IllegalQualChar,
+            ]
+        );
+        assert_eq!(report.issues[4].record, 2);
+    }
+
+    #[test]
+    fn truncated_record_is_found() {
+        let fastq = format!("{}@r1\nACGT\n+\n", record(0, &["ACGT"]));
+        let report = validate(fastq.as_bytes());
+        assert_eq!(report.num_issues, 1);
+        assert_eq!(report.issues[0].kind, IssueKind::TruncatedRecord);
+        assert_eq!(report.issues[0].record, 2);
+    }
+}